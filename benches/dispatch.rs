@@ -0,0 +1,196 @@
+//! Measures how many updates the dispatcher gets through when they are
+//! handled one after another behind a single lock, as the bot used to do,
+//! and when many are in flight at once.
+//!
+//! Run with `cargo bench --bench dispatch`.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Weak};
+use std::time::{Duration, Instant};
+
+use indexmap::IndexMap;
+use tokio::sync::Mutex;
+
+use r2d2::Pool;
+use r2d2_sqlite::SqliteConnectionManager;
+
+use teloxide::prelude::{Message, Update};
+use teloxide::Bot;
+
+use tebot::audit::manager::AuditLog;
+use tebot::bot::command::{self, CommandMetadata, ReplyRequirement};
+use tebot::bot::config::Config;
+use tebot::bot::context::Context;
+use tebot::bot::dispatcher::{self, Dispatcher, SharedDispatcher};
+use tebot::bot::{handler, plugin, shutdown::Shutdown};
+use tebot::permissions::admins::AdminResolver;
+use tebot::permissions::blocklist::BlockList;
+use tebot::permissions::manager::PermissionManager;
+use tebot::permissions::roles::RoleManager;
+use tebot::permissions::types::Permission;
+use tebot::settings::manager::SettingsManager;
+use tebot::utils::style::DefaultStyle;
+
+const UPDATES: usize = 2000;
+const CONCURRENCY: &[usize] = &[1, 8, 64];
+
+/// Number of updates that made it to the command handler.
+static HANDLED: AtomicUsize = AtomicUsize::new(0);
+
+struct BenchPlugin;
+
+async fn on_ping(
+  _bot: Bot,
+  _msg: Message,
+  _cmd: command::Command,
+  _ctx: Weak<Mutex<Context>>,
+) -> anyhow::Result<()> {
+  HANDLED.fetch_add(1, Ordering::Relaxed);
+  Ok(())
+}
+
+impl plugin::Plugin for BenchPlugin {
+  fn name(&self) -> &str {
+    "bench"
+  }
+
+  fn commands(&self) -> IndexMap<String, CommandMetadata> {
+    let mut cmds = IndexMap::new();
+    cmds.insert(
+      "ping".to_string(),
+      CommandMetadata::new(
+        Permission::USER,
+        "Does nothing".to_string(),
+        ReplyRequirement::None,
+        vec![],
+        Arc::new(|_bot, _msg, _cmd, _ctx| Box::pin(on_ping(_bot, _msg, _cmd, _ctx))),
+      ),
+    );
+    cmds
+  }
+
+  fn update_handlers(&self) -> Vec<handler::UpdateHandler> {
+    Vec::new()
+  }
+}
+
+fn update(id: usize) -> Update {
+  let user = 1000 + (id % 50) as i64;
+
+  // `Update` flattens its kind, which fails to deserialize from a `Value`
+  // and silently yields `UpdateKind::Error`, so go through a string.
+  let json = serde_json::json!({
+    "update_id": id,
+    "message": {
+      "message_id": id,
+      "date": 0,
+      "chat": { "id": user, "type": "private", "first_name": "bench" },
+      "from": { "id": user, "is_bot": false, "first_name": "bench" },
+      "text": "/ping"
+    }
+  });
+
+  serde_json::from_str(&json.to_string()).expect("valid update")
+}
+
+async fn setup(
+  db_path: &std::path::Path,
+) -> anyhow::Result<(Arc<Mutex<Context>>, SharedDispatcher)> {
+  let cfg = Config::new_shared(
+    String::new(),
+    vec![command::Prefix::Literal("/".to_string())],
+    false,
+  );
+  let pool = Arc::new(Pool::new(SqliteConnectionManager::file(db_path))?);
+  let bot = Arc::new(Bot::new("0:bench"));
+  let dp = Dispatcher::new_shared(Weak::new());
+
+  let ctx = Arc::new(Mutex::new(Context::new(
+    cfg,
+    pool.clone(),
+    PermissionManager::new_shared(pool.clone(), Permission::USER)?,
+    AdminResolver::new_shared(Duration::from_secs(300)),
+    RoleManager::new_shared(pool.clone())?,
+    BlockList::new_shared(pool.clone())?,
+    AuditLog::new_shared(pool.clone())?,
+    SettingsManager::new_shared(pool.clone())?,
+    bot,
+    dp.clone(),
+    Shutdown::new_shared(Duration::from_secs(30)),
+    Arc::new(DefaultStyle),
+  )));
+
+  Arc::make_mut(&mut *dp.write().await).context = Arc::downgrade(&ctx);
+  plugin::register_all(dp.clone(), vec![Box::new(BenchPlugin)]).await;
+
+  Ok((ctx, dp))
+}
+
+/// Handles `UPDATES` updates with up to `concurrency` in flight, taking
+/// `gate` around each one when given. Returns updates per second.
+async fn run(
+  dp: &SharedDispatcher,
+  concurrency: usize,
+  gate: Option<Arc<Mutex<()>>>,
+) -> f64 {
+  let bot = Bot::new("0:bench");
+  HANDLED.store(0, Ordering::Relaxed);
+  let started = Instant::now();
+
+  let mut workers = tokio::task::JoinSet::new();
+  for worker in 0..concurrency {
+    let dp = dp.clone();
+    let bot = bot.clone();
+    let gate = gate.clone();
+
+    workers.spawn(async move {
+      for id in (worker..UPDATES).step_by(concurrency) {
+        let _gate = match &gate {
+          Some(gate) => Some(gate.lock().await),
+          None => None,
+        };
+
+        let dp = dispatcher::current(&dp).await;
+        if let Err(err) = dp.handle_update(bot.clone(), update(id)).await {
+          eprintln!("update {} failed: {:?}", id, err);
+        }
+      }
+    });
+  }
+
+  while workers.join_next().await.is_some() {}
+  dispatcher::current(dp)
+    .await
+    .tasks
+    .drain(Duration::from_secs(30))
+    .await;
+
+  let elapsed = started.elapsed();
+  assert_eq!(HANDLED.load(Ordering::Relaxed), UPDATES, "not every update reached the command");
+
+  UPDATES as f64 / elapsed.as_secs_f64()
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+  let db_path = std::env::temp_dir().join(format!("tebot-bench-{}.db", std::process::id()));
+  let (_ctx, dp) = setup(&db_path).await?;
+
+  // Warm up the connection pool and caches.
+  run(&dp, 8, None).await;
+
+  for &concurrency in CONCURRENCY {
+    let serialized = run(&dp, concurrency, Some(Arc::new(Mutex::new(())))).await;
+    let concurrent = run(&dp, concurrency, None).await;
+
+    println!(
+      "{:>3} in flight: {:>9.0} updates/s serialized, {:>9.0} updates/s concurrent",
+      concurrency, serialized, concurrent
+    );
+  }
+
+  drop(dp);
+  let _ = std::fs::remove_file(&db_path);
+
+  Ok(())
+}
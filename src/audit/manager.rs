@@ -0,0 +1,200 @@
+use chrono::{DateTime, Utc};
+use rusqlite::params;
+use std::sync::Arc;
+
+use r2d2::Pool;
+use r2d2_sqlite::SqliteConnectionManager;
+
+use teloxide::types::{ChatId, UserId};
+
+/// A single audit record. Which fields are set depends on the action.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AuditEntry {
+  pub id: i64,
+  pub at: DateTime<Utc>,
+  pub chat_id: Option<ChatId>,
+
+  /// Who did it.
+  pub actor: Option<UserId>,
+
+  /// What was done, e.g. `permission.set` or `command`.
+  pub action: String,
+
+  /// Whose permission changed, for permission actions.
+  pub target: Option<UserId>,
+
+  /// Command that caused the entry.
+  pub command: Option<String>,
+
+  pub before: Option<String>,
+  pub after: Option<String>,
+
+  /// Free-form context, such as the arguments a command was given.
+  pub detail: Option<String>,
+}
+
+/// Narrows an audit query. Unset fields match everything.
+#[derive(Debug, Clone, Default)]
+pub struct AuditFilter {
+  /// Matches entries where the user is either the actor or the target.
+  pub user: Option<UserId>,
+  pub command: Option<String>,
+  pub since: Option<DateTime<Utc>>,
+  pub until: Option<DateTime<Utc>>,
+}
+
+impl AuditFilter {
+  fn clause(&self) -> (String, Vec<rusqlite::types::Value>) {
+    use rusqlite::types::Value;
+
+    let mut conds = Vec::new();
+    let mut values = Vec::new();
+
+    if let Some(user) = self.user {
+      values.push(Value::Text(user.0.to_string()));
+      conds.push(format!("(actor = ?{0} OR target = ?{0})", values.len()));
+    }
+
+    if let Some(command) = &self.command {
+      values.push(Value::Text(command.clone()));
+      conds.push(format!("command = ?{}", values.len()));
+    }
+
+    if let Some(since) = self.since {
+      values.push(Value::Integer(since.timestamp()));
+      conds.push(format!("at >= ?{}", values.len()));
+    }
+
+    if let Some(until) = self.until {
+      values.push(Value::Integer(until.timestamp()));
+      conds.push(format!("at <= ?{}", values.len()));
+    }
+
+    let clause = if conds.is_empty() {
+      String::new()
+    } else {
+      format!("WHERE {}", conds.join(" AND "))
+    };
+
+    (clause, values)
+  }
+}
+
+/// Append-only record of permission changes and privileged commands.
+#[derive(Debug, Clone)]
+pub struct AuditLog {
+  pub db: Arc<Pool<SqliteConnectionManager>>,
+}
+
+impl AuditLog {
+  pub fn new(db: Arc<Pool<SqliteConnectionManager>>) -> anyhow::Result<Self> {
+    let mgr = Self { db };
+    mgr.init_schema()?;
+    Ok(mgr)
+  }
+
+  pub fn new_shared(db: Arc<Pool<SqliteConnectionManager>>) -> anyhow::Result<Arc<Self>> {
+    let mgr = Self::new(db)?;
+    Ok(Arc::new(mgr))
+  }
+
+  fn init_schema(&self) -> anyhow::Result<()> {
+    let conn = self.db.get()?;
+    conn.execute_batch(
+      "CREATE TABLE IF NOT EXISTS audit_log (
+                id      INTEGER PRIMARY KEY AUTOINCREMENT,
+                at      INTEGER NOT NULL,
+                chat_id INTEGER,
+                actor   TEXT,
+                action  TEXT NOT NULL,
+                target  TEXT,
+                command TEXT,
+                before  TEXT,
+                after   TEXT,
+                detail  TEXT
+            );
+       CREATE INDEX IF NOT EXISTS audit_log_at ON audit_log (at);
+       CREATE TRIGGER IF NOT EXISTS audit_log_no_update
+            BEFORE UPDATE ON audit_log
+            BEGIN SELECT RAISE(ABORT, 'audit_log is append-only'); END;
+       CREATE TRIGGER IF NOT EXISTS audit_log_no_delete
+            BEFORE DELETE ON audit_log
+            BEGIN SELECT RAISE(ABORT, 'audit_log is append-only'); END;",
+    )?;
+    Ok(())
+  }
+
+  /// Appends an entry; `id` and `at` are assigned here.
+  pub fn record(&self, entry: AuditEntry) -> anyhow::Result<()> {
+    let conn = self.db.get()?;
+    conn.execute(
+      "INSERT INTO audit_log
+               (at, chat_id, actor, action, target, command, before, after, detail)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+      params![
+        Utc::now().timestamp(),
+        entry.chat_id.map(|id| id.0),
+        entry.actor.map(|id| id.0.to_string()),
+        entry.action,
+        entry.target.map(|id| id.0.to_string()),
+        entry.command,
+        entry.before,
+        entry.after,
+        entry.detail,
+      ],
+    )?;
+
+    log::trace!("audit: {:?}", entry);
+
+    Ok(())
+  }
+
+  pub fn count(&self, filter: &AuditFilter) -> anyhow::Result<usize> {
+    let (clause, values) = filter.clause();
+
+    let conn = self.db.get()?;
+    let count = conn.query_row(
+      &format!("SELECT COUNT(*) FROM audit_log {}", clause),
+      rusqlite::params_from_iter(values),
+      |row| row.get::<_, i64>(0),
+    )?;
+
+    Ok(count as usize)
+  }
+
+  /// Matching entries, newest first.
+  pub fn query(
+    &self,
+    filter: &AuditFilter,
+    limit: usize,
+    offset: usize,
+  ) -> anyhow::Result<Vec<AuditEntry>> {
+    let (clause, values) = filter.clause();
+
+    let conn = self.db.get()?;
+    let mut stmt = conn.prepare(&format!(
+      "SELECT id, at, chat_id, actor, action, target, command, before, after, detail
+             FROM audit_log {} ORDER BY id DESC LIMIT {} OFFSET {}",
+      clause, limit, offset
+    ))?;
+
+    let parse_user = |raw: Option<String>| raw.and_then(|raw| raw.parse().ok()).map(UserId);
+
+    let rows = stmt.query_map(rusqlite::params_from_iter(values), |row| {
+      Ok(AuditEntry {
+        id: row.get(0)?,
+        at: DateTime::from_timestamp(row.get(1)?, 0).unwrap_or_default(),
+        chat_id: row.get::<_, Option<i64>>(2)?.map(ChatId),
+        actor: parse_user(row.get(3)?),
+        action: row.get(4)?,
+        target: parse_user(row.get(5)?),
+        command: row.get(6)?,
+        before: row.get(7)?,
+        after: row.get(8)?,
+        detail: row.get(9)?,
+      })
+    })?;
+
+    Ok(rows.collect::<Result<_, _>>()?)
+  }
+}
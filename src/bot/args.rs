@@ -0,0 +1,587 @@
+use std::time::Duration;
+
+use indexmap::IndexMap;
+use teloxide::prelude::{Message, UserId};
+
+use crate::permissions::types::Permission;
+use crate::utils::parsers;
+
+use super::command::{ArgMetadata, ArgRequirement, Command, CommandMetadata, ReplyRequirement};
+
+#[derive(Clone, Debug, PartialEq, PartialOrd, Ord, Eq)]
+pub enum ArgKind {
+  String,
+  Integer,
+  UserId,
+  Permission,
+  Duration,
+  Choice(Vec<String>),
+  Rest,
+}
+
+impl ArgKind {
+  pub fn describe(&self) -> String {
+    match self {
+      ArgKind::String => "string".to_string(),
+      ArgKind::Integer => "integer".to_string(),
+      ArgKind::UserId => "user id".to_string(),
+      ArgKind::Permission => "permission".to_string(),
+      ArgKind::Duration => "duration".to_string(),
+      ArgKind::Choice(choices) => format!("one of {}", choices.join("|")),
+      ArgKind::Rest => "text".to_string(),
+    }
+  }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ArgValue {
+  String(String),
+  Integer(i64),
+  UserId(UserId),
+  Permission(Permission),
+  Duration(Duration),
+  Flag,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum ArgError {
+  #[error("{0} not specified")]
+  Missing(String),
+
+  #[error("invalid {name}: expected {expected}, got '{value}'")]
+  Invalid {
+    name: String,
+    expected: String,
+    value: String,
+  },
+
+  #[error("this command must be sent as a reply")]
+  ReplyRequired,
+
+  #[error("too many arguments: unexpected '{0}'")]
+  TooMany(String),
+
+  #[error("unknown option {0}")]
+  UnknownOption(String),
+
+  #[error("option --{0} requires a value")]
+  MissingValue(String),
+
+  #[error("option --{0} does not take a value")]
+  UnexpectedValue(String),
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Args {
+  values: IndexMap<String, ArgValue>,
+}
+
+impl Args {
+  pub fn insert(
+    &mut self,
+    name: &str,
+    value: ArgValue,
+  ) {
+    self.values.insert(name.to_string(), value);
+  }
+
+  pub fn get(&self, name: &str) -> Option<&ArgValue> {
+    self.values.get(name)
+  }
+
+  pub fn contains(&self, name: &str) -> bool {
+    self.values.contains_key(name)
+  }
+
+  pub fn flag(&self, name: &str) -> bool {
+    matches!(self.get(name), Some(ArgValue::Flag))
+  }
+
+  pub fn str(&self, name: &str) -> Option<&str> {
+    match self.get(name) {
+      Some(ArgValue::String(s)) => Some(s),
+      _ => None,
+    }
+  }
+
+  pub fn integer(&self, name: &str) -> Option<i64> {
+    match self.get(name) {
+      Some(ArgValue::Integer(i)) => Some(*i),
+      _ => None,
+    }
+  }
+
+  pub fn user_id(&self, name: &str) -> Option<UserId> {
+    match self.get(name) {
+      Some(ArgValue::UserId(id)) => Some(*id),
+      _ => None,
+    }
+  }
+
+  pub fn permission(&self, name: &str) -> Option<Permission> {
+    match self.get(name) {
+      Some(ArgValue::Permission(perm)) => Some(*perm),
+      _ => None,
+    }
+  }
+
+  pub fn duration(&self, name: &str) -> Option<Duration> {
+    match self.get(name) {
+      Some(ArgValue::Duration(dur)) => Some(*dur),
+      _ => None,
+    }
+  }
+}
+
+async fn convert(
+  name: &str,
+  kind: &ArgKind,
+  raw: &str,
+) -> Result<ArgValue, ArgError> {
+  let invalid = || ArgError::Invalid {
+    name: name.to_string(),
+    expected: kind.describe(),
+    value: raw.to_string(),
+  };
+
+  match kind {
+    ArgKind::String | ArgKind::Rest => Ok(ArgValue::String(raw.to_string())),
+    ArgKind::Integer => raw
+      .parse::<i64>()
+      .map(ArgValue::Integer)
+      .map_err(|_| invalid()),
+    ArgKind::UserId => parsers::parse_uid(raw)
+      .await
+      .map(ArgValue::UserId)
+      .map_err(|_| invalid()),
+    ArgKind::Permission => parsers::parse_permission(raw)
+      .await
+      .map(ArgValue::Permission)
+      .map_err(|_| invalid()),
+    ArgKind::Duration => parsers::parse_duration(raw)
+      .await
+      .map(ArgValue::Duration)
+      .map_err(|_| invalid()),
+    ArgKind::Choice(choices) => choices
+      .iter()
+      .find(|c| c.eq_ignore_ascii_case(raw))
+      .map(|c| ArgValue::String(c.clone()))
+      .ok_or_else(invalid),
+  }
+}
+
+fn applies(
+  arg: &ArgMetadata,
+  reply: Option<&Message>,
+) -> bool {
+  match arg.requirement {
+    ArgRequirement::OnlyWithReply => reply.is_some(),
+    ArgRequirement::OnlyWithoutReply => reply.is_none(),
+    ArgRequirement::Optional | ArgRequirement::Required => true,
+  }
+}
+
+/// Pulls declared options out of the command arguments, returning the
+/// indices of the remaining positional arguments.
+///
+/// Option parsing stops at `--` and at the start of a `Rest` argument, so
+/// free text is never mistaken for options. Arguments whose leading dashes
+/// are quoted or escaped and unknown short options (e.g. negative numbers)
+/// are kept as positionals, while `--reason="two words"` is still an option.
+async fn parse_opts(
+  meta: &CommandMetadata,
+  cmd: &Command,
+  rest_at: Option<usize>,
+  args: &mut Args,
+) -> Result<Vec<usize>, ArgError> {
+  let mut positional = Vec::new();
+  let mut options_done = false;
+  let mut idx = 0;
+
+  while idx < cmd.args.len() {
+    let token = &cmd.args[idx];
+
+    if Some(positional.len()) == rest_at {
+      options_done = true;
+    }
+
+    if options_done || cmd.has_quoted_prefix(idx) {
+      positional.push(idx);
+      idx += 1;
+      continue;
+    }
+
+    if token == "--" {
+      options_done = true;
+      idx += 1;
+      continue;
+    }
+
+    let (opt, inline) = if let Some(long) = token.strip_prefix("--") {
+      let (name, inline) = match long.split_once('=') {
+        Some((name, value)) => (name, Some(value)),
+        None => (long, None),
+      };
+
+      let opt = meta
+        .find_opt(name)
+        .ok_or_else(|| ArgError::UnknownOption(format!("--{}", name)))?;
+      (opt, inline)
+    } else {
+      let mut chars = token.chars();
+      let short = match (chars.next(), chars.next(), chars.next()) {
+        (Some('-'), Some(short), None) => meta.find_short_opt(short),
+        _ => None,
+      };
+
+      match short {
+        Some(opt) => (opt, None),
+        None => {
+          positional.push(idx);
+          idx += 1;
+          continue;
+        }
+      }
+    };
+
+    let value = match (&opt.kind, inline) {
+      (None, None) => ArgValue::Flag,
+      (None, Some(_)) => return Err(ArgError::UnexpectedValue(opt.name.clone())),
+      (Some(kind), Some(value)) => convert(&opt.name, kind, value).await?,
+      (Some(kind), None) => {
+        idx += 1;
+        let value = cmd
+          .args
+          .get(idx)
+          .ok_or_else(|| ArgError::MissingValue(opt.name.clone()))?;
+        convert(&opt.name, kind, value).await?
+      }
+    };
+
+    args.insert(&opt.name, value);
+    idx += 1;
+  }
+
+  Ok(positional)
+}
+
+/// Validates the message against the command metadata and converts the
+/// command's options and positional arguments into a typed [`Args`] bag.
+///
+/// Arguments that only apply with (or without) a reply are skipped when the
+/// message does not match, and are mandatory otherwise. A `UserId` argument
+/// that is skipped or omitted is filled from the sender of the replied
+/// message. A `Rest` argument takes the raw remainder of the message.
+/// Surplus positional arguments are only rejected for strict commands.
+pub async fn parse(
+  meta: &CommandMetadata,
+  cmd: &Command,
+  reply: Option<&Message>,
+) -> Result<Args, ArgError> {
+  if meta.reply == ReplyRequirement::Required && reply.is_none() {
+    return Err(ArgError::ReplyRequired);
+  }
+
+  let reply_user = reply.and_then(|r| r.from.as_ref()).map(|u| u.id);
+
+  let mut args = Args::default();
+
+  let rest_at = meta
+    .args
+    .iter()
+    .filter(|arg| applies(arg, reply))
+    .position(|arg| arg.kind == ArgKind::Rest);
+  let positional = parse_opts(meta, cmd, rest_at, &mut args).await?;
+
+  let mut pos = 0;
+
+  for arg in &meta.args {
+    if !applies(arg, reply) {
+      if arg.kind == ArgKind::UserId
+        && let Some(user_id) = reply_user
+      {
+        args.insert(&arg.name, ArgValue::UserId(user_id));
+      }
+      continue;
+    }
+
+    let value = match arg.kind {
+      ArgKind::Rest if pos < positional.len() => {
+        let rest = cmd.rest(positional[pos]).to_string();
+        pos = positional.len();
+        Some(rest)
+      }
+      _ => {
+        let value = positional.get(pos).map(|&idx| cmd.args[idx].clone());
+        pos += 1;
+        value
+      }
+    };
+
+    match value {
+      Some(value) => {
+        let converted = convert(&arg.name, &arg.kind, &value).await?;
+        args.insert(&arg.name, converted);
+      }
+      None if arg.requirement != ArgRequirement::Optional => {
+        return Err(ArgError::Missing(arg.name.clone()));
+      }
+      None => {
+        if arg.kind == ArgKind::UserId
+          && let Some(user_id) = reply_user
+        {
+          args.insert(&arg.name, ArgValue::UserId(user_id));
+        }
+      }
+    }
+  }
+
+  if meta.strict
+    && let Some(&extra) = positional.get(pos)
+  {
+    return Err(ArgError::TooMany(cmd.args[extra].clone()));
+  }
+
+  log::trace!("parsed typed args {:?}", args);
+
+  Ok(args)
+}
+
+#[cfg(test)]
+mod tests {
+  use std::sync::Arc;
+
+  use super::*;
+  use crate::bot::command::OptMetadata;
+
+  fn meta(
+    reply: ReplyRequirement,
+    args: Vec<(&str, ArgKind, ArgRequirement)>,
+    opts: Vec<(&str, Option<char>, Option<ArgKind>)>,
+  ) -> CommandMetadata {
+    let args = args
+      .into_iter()
+      .map(|(name, kind, req)| ArgMetadata::new(name.to_string(), String::new(), kind, req))
+      .collect();
+    let opts = opts
+      .into_iter()
+      .map(|(name, short, kind)| OptMetadata::new(name.to_string(), short, String::new(), kind))
+      .collect();
+
+    CommandMetadata::new(
+      Permission::NONE,
+      String::new(),
+      reply,
+      args,
+      Arc::new(|_bot, _msg, _cmd, _ctx| Box::pin(async { Ok(()) })),
+    )
+    .with_opts(opts)
+  }
+
+  fn cmd(s: &str) -> Command {
+    Command::with_prefix(s, "/").unwrap().unwrap()
+  }
+
+  fn message(from: u64) -> Message {
+    serde_json::from_value(serde_json::json!({
+      "message_id": 1,
+      "date": 0,
+      "chat": { "id": 1, "type": "private", "first_name": "chat" },
+      "from": { "id": from, "is_bot": false, "first_name": "user" },
+      "text": "hello",
+    }))
+    .unwrap()
+  }
+
+  #[tokio::test]
+  async fn converts_positional_arguments() {
+    let meta = meta(
+      ReplyRequirement::None,
+      vec![
+        ("user_id", ArgKind::UserId, ArgRequirement::Required),
+        ("perm", ArgKind::Permission, ArgRequirement::Required),
+        ("for", ArgKind::Duration, ArgRequirement::Optional),
+      ],
+      vec![],
+    );
+
+    let args = parse(&meta, &cmd("/grant 42 admin 1h30m"), None).await.unwrap();
+    assert_eq!(args.user_id("user_id"), Some(UserId(42)));
+    assert_eq!(args.permission("perm"), Some(Permission::ADMIN));
+    assert_eq!(args.duration("for"), Some(Duration::from_secs(5400)));
+
+    let err = parse(&meta, &cmd("/grant abc admin"), None).await.unwrap_err();
+    assert!(matches!(err, ArgError::Invalid { name, .. } if name == "user_id"));
+
+    let err = parse(&meta, &cmd("/grant 42"), None).await.unwrap_err();
+    assert!(matches!(err, ArgError::Missing(name) if name == "perm"));
+  }
+
+  #[tokio::test]
+  async fn parses_long_and_short_options() {
+    let meta = meta(
+      ReplyRequirement::None,
+      vec![],
+      vec![
+        ("json", Some('j'), None),
+        ("level", Some('l'), Some(ArgKind::Permission)),
+      ],
+    );
+
+    let args = parse(&meta, &cmd("/show --json --level=admin"), None).await.unwrap();
+    assert!(args.flag("json"));
+    assert_eq!(args.permission("level"), Some(Permission::ADMIN));
+
+    let args = parse(&meta, &cmd("/show -l owner -j"), None).await.unwrap();
+    assert!(args.flag("json"));
+    assert_eq!(args.permission("level"), Some(Permission::OWNER));
+
+    let args = parse(&meta, &cmd("/show --level owner"), None).await.unwrap();
+    assert_eq!(args.permission("level"), Some(Permission::OWNER));
+  }
+
+  #[tokio::test]
+  async fn rejects_malformed_options() {
+    let meta = meta(
+      ReplyRequirement::None,
+      vec![],
+      vec![
+        ("json", Some('j'), None),
+        ("level", Some('l'), Some(ArgKind::Permission)),
+      ],
+    );
+
+    let err = parse(&meta, &cmd("/show --nope"), None).await.unwrap_err();
+    assert!(matches!(err, ArgError::UnknownOption(opt) if opt == "--nope"));
+
+    let err = parse(&meta, &cmd("/show --json=yes"), None).await.unwrap_err();
+    assert!(matches!(err, ArgError::UnexpectedValue(opt) if opt == "json"));
+
+    let err = parse(&meta, &cmd("/show --level"), None).await.unwrap_err();
+    assert!(matches!(err, ArgError::MissingValue(opt) if opt == "level"));
+  }
+
+  #[tokio::test]
+  async fn double_dash_ends_options() {
+    let meta = meta(
+      ReplyRequirement::None,
+      vec![("text", ArgKind::String, ArgRequirement::Required)],
+      vec![("json", Some('j'), None)],
+    );
+
+    let args = parse(&meta, &cmd("/echo -- --json"), None).await.unwrap();
+    assert!(!args.flag("json"));
+    assert_eq!(args.str("text"), Some("--json"));
+  }
+
+  #[tokio::test]
+  async fn quoted_tokens_are_never_options() {
+    let meta = meta(
+      ReplyRequirement::None,
+      vec![("text", ArgKind::String, ArgRequirement::Required)],
+      vec![("json", Some('j'), None)],
+    );
+
+    let args = parse(&meta, &cmd(r#"/echo "--json""#), None).await.unwrap();
+    assert!(!args.flag("json"));
+    assert_eq!(args.str("text"), Some("--json"));
+
+    let args = parse(&meta, &cmd(r"/echo \-j"), None).await.unwrap();
+    assert!(!args.flag("json"));
+    assert_eq!(args.str("text"), Some("-j"));
+  }
+
+  #[tokio::test]
+  async fn option_values_may_be_quoted() {
+    let meta = meta(
+      ReplyRequirement::None,
+      vec![],
+      vec![
+        ("reason", Some('r'), Some(ArgKind::String)),
+        ("for", Some('f'), Some(ArgKind::Duration)),
+      ],
+    );
+
+    let args = parse(&meta, &cmd(r#"/ban --reason="two words" --for="1h""#), None)
+      .await
+      .unwrap();
+    assert_eq!(args.str("reason"), Some("two words"));
+    assert_eq!(args.duration("for"), Some(Duration::from_secs(3600)));
+
+    let args = parse(&meta, &cmd(r#"/ban -r 'two words'"#), None).await.unwrap();
+    assert_eq!(args.str("reason"), Some("two words"));
+  }
+
+  #[tokio::test]
+  async fn negative_numbers_stay_positional() {
+    let meta = meta(
+      ReplyRequirement::None,
+      vec![("chat_id", ArgKind::Integer, ArgRequirement::Required)],
+      vec![("chat", Some('c'), None)],
+    );
+
+    let args = parse(&meta, &cmd("/ban -c -1001234"), None).await.unwrap();
+    assert!(args.flag("chat"));
+    assert_eq!(args.integer("chat_id"), Some(-1001234));
+  }
+
+  #[tokio::test]
+  async fn rest_takes_the_raw_remainder() {
+    let meta = meta(
+      ReplyRequirement::None,
+      vec![
+        ("user_id", ArgKind::UserId, ArgRequirement::Required),
+        ("reason", ArgKind::Rest, ArgRequirement::Optional),
+      ],
+      vec![("json", Some('j'), None)],
+    );
+
+    let args = parse(&meta, &cmd(r#"/ban -j 42 spam  "links" -j"#), None).await.unwrap();
+    assert!(args.flag("json"));
+    assert_eq!(args.str("reason"), Some(r#"spam  "links" -j"#));
+  }
+
+  #[tokio::test]
+  async fn surplus_arguments_are_only_rejected_when_strict() {
+    let lenient = meta(
+      ReplyRequirement::None,
+      vec![("user_id", ArgKind::UserId, ArgRequirement::Required)],
+      vec![],
+    );
+    assert!(parse(&lenient, &cmd("/reset 42 extra"), None).await.is_ok());
+
+    let strict = lenient.with_strict_args();
+    let err = parse(&strict, &cmd("/reset 42 extra"), None).await.unwrap_err();
+    assert!(matches!(err, ArgError::TooMany(arg) if arg == "extra"));
+  }
+
+  #[tokio::test]
+  async fn enforces_reply_requirements() {
+    let required = meta(ReplyRequirement::Required, vec![], vec![]);
+    let err = parse(&required, &cmd("/import"), None).await.unwrap_err();
+    assert!(matches!(err, ArgError::ReplyRequired));
+    assert!(parse(&required, &cmd("/import"), Some(&message(7))).await.is_ok());
+
+    let none = meta(ReplyRequirement::None, vec![], vec![]);
+    assert!(parse(&none, &cmd("/ping"), Some(&message(7))).await.is_ok());
+  }
+
+  #[tokio::test]
+  async fn fills_user_id_from_the_reply() {
+    let meta = meta(
+      ReplyRequirement::Optional,
+      vec![
+        ("user_id", ArgKind::UserId, ArgRequirement::OnlyWithoutReply),
+        ("perm", ArgKind::Permission, ArgRequirement::Required),
+      ],
+      vec![],
+    );
+
+    let args = parse(&meta, &cmd("/grant admin"), Some(&message(7))).await.unwrap();
+    assert_eq!(args.user_id("user_id"), Some(UserId(7)));
+    assert_eq!(args.permission("perm"), Some(Permission::ADMIN));
+
+    let err = parse(&meta, &cmd("/grant admin"), None).await.unwrap_err();
+    assert!(matches!(err, ArgError::Invalid { name, .. } if name == "user_id"));
+  }
+}
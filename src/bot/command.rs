@@ -1,80 +1,176 @@
 use derivative::Derivative;
+use serde::{Deserialize, Serialize};
 
 use crate::permissions::types::Permission;
 
+use super::args::{self, ArgKind};
 use super::handler;
+use super::lexer;
+
+/// A way of addressing the bot at the start of a message.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Prefix {
+  /// A literal string such as `/`, `!!` or `tb `.
+  Literal(String),
+
+  /// A mention of the bot's own username, e.g. `@tebot help`.
+  Mention,
+}
+
+impl Prefix {
+  /// Returns the number of leading bytes of `s` matched by this prefix.
+  pub fn matches(
+    &self,
+    s: &str,
+    username: Option<&str>,
+  ) -> Option<usize> {
+    match self {
+      Prefix::Literal(lit) if !lit.is_empty() && s.starts_with(lit.as_str()) => Some(lit.len()),
+      Prefix::Literal(_) => None,
+      Prefix::Mention => {
+        let mention = format!("@{}", username?);
+        let head = s.get(..mention.len())?;
+        let tail = &s[mention.len()..];
+
+        (head.eq_ignore_ascii_case(&mention)
+          && tail.starts_with(char::is_whitespace))
+        .then_some(mention.len())
+      }
+    }
+  }
+
+  /// Renders the prefix the way a user would type it.
+  pub fn display(
+    &self,
+    username: Option<&str>,
+  ) -> String {
+    match self {
+      Prefix::Literal(lit) => lit.clone(),
+      Prefix::Mention => format!("@{} ", username.unwrap_or("bot")),
+    }
+  }
+}
 
 pub struct Command {
-  pub prefix: char,
+  pub prefix: String,
   pub name: String,
   pub args: Vec<String>,
+
+  /// Unparsed text following the command name.
+  pub raw: String,
+
+  /// Bot username from the `/cmd@username` form used in group chats.
+  pub target: Option<String>,
+
+  /// Typed arguments, filled by the dispatcher from the command metadata
+  /// before the handler runs.
+  pub values: args::Args,
+
+  tokens: Vec<lexer::Token>,
+}
+
+#[derive(thiserror::Error, Debug, Clone)]
+#[error("{source}")]
+pub struct ParseError {
+  pub prefix: String,
+  pub name: String,
+  pub target: Option<String>,
+
+  #[source]
+  pub source: lexer::LexError,
 }
 
 impl Command {
-  pub fn with_prefix(s: &str, prefix: char) -> Option<Self> {
-    let mut chars = s.chars().peekable();
-    let mut parts = Vec::new();
-    let mut current = String::new();
-    let mut in_quotes = false;
-
-    if chars.peek() != Some(&prefix) {
-      log::trace!("string '{}' does not start with prefix '{}'", s, prefix);
-      return None;
-    }
-    chars.next();
-
-    while let Some(&c) = chars.peek() {
-      match c {
-        '"' => {
-          in_quotes = !in_quotes;
-          chars.next();
-        }
-        ' ' if !in_quotes => {
-          if !current.is_empty() {
-            parts.push(current.clone());
-            current.clear();
-          }
-          chars.next();
-        }
-        _ => {
-          current.push(c);
-          chars.next();
-        }
+  pub fn with_prefix(
+    s: &str,
+    prefix: &str,
+  ) -> Result<Option<Self>, ParseError> {
+    let body = match s.strip_prefix(prefix) {
+      Some(body) => body.trim_start(),
+      None => {
+        log::trace!("string '{}' does not start with prefix '{}'", s, prefix);
+        return Ok(None);
       }
-    }
+    };
+
+    let name_end = body.find(char::is_whitespace).unwrap_or(body.len());
+    let (head, raw) = body.split_at(name_end);
 
-    if !current.is_empty() {
-      parts.push(current);
+    if head.is_empty() {
+      log::trace!("no command name found in string '{}'", s);
+      return Ok(None);
     }
 
-    let name = match parts.get(0) {
-      Some(n) => n.clone(),
-      None => {
-        log::trace!("no command name found in string '{}'", s);
-        return None;
-      }
+    let (name, target) = match head.split_once('@') {
+      Some((name, target)) => (name.to_string(), Some(target.to_string())),
+      None => (head.to_string(), None),
     };
-    let args = parts.into_iter().skip(1).collect();
 
-    log::trace!("parsed command '{}' with args {:?}", name, args);
+    let tokens = lexer::tokenize(raw).map_err(|source| ParseError {
+      prefix: prefix.to_string(),
+      name: name.clone(),
+      target: target.clone(),
+      source,
+    })?;
+
+    let args: Vec<String> = tokens.iter().map(|t| t.value.clone()).collect();
+
+    log::trace!(
+      "parsed command '{}' (target {:?}) with args {:?}",
+      name,
+      target,
+      args
+    );
+
+    Ok(Some(Self {
+      prefix: prefix.to_string(),
+      name,
+      args,
+      raw: raw.to_string(),
+      target,
+      values: args::Args::default(),
+      tokens,
+    }))
+  }
+
+  /// Parses `s` using the longest matching prefix, so `!!` wins over `!`.
+  pub fn with_prefixes(
+    s: &str,
+    allowed: &[Prefix],
+    username: Option<&str>,
+  ) -> Result<Option<Self>, ParseError> {
+    let matched = allowed
+      .iter()
+      .filter_map(|prefix| prefix.matches(s, username))
+      .max();
 
-    Some(Self { prefix, name, args })
+    match matched {
+      Some(len) => {
+        let prefix = &s[..len];
+        log::trace!("string '{}' matches allowed prefixes, using '{}'", s, prefix);
+        Self::with_prefix(s, prefix)
+      }
+      None => {
+        log::trace!("string '{}' does not match any allowed prefixes", s);
+        Ok(None)
+      }
+    }
   }
 
-  pub fn with_prefixes<T>(s: &str, allowed: T) -> Option<Self>
-  where
-    T: AsRef<[char]>,
-  {
-    let mut chars = s.chars();
-    let first = chars.next()?;
-    if allowed.as_ref().contains(&first) {
-      log::trace!("string '{}' matches allowed prefixes, using '{}'", s, first);
-      Self::with_prefix(s, first)
-    } else {
-      log::trace!("string '{}' does not match any allowed prefixes", s);
-      None
+  /// Returns the raw, unparsed text starting at the argument with the given
+  /// index, keeping the original quoting and spacing.
+  pub fn rest(&self, index: usize) -> &str {
+    match self.tokens.get(index) {
+      Some(token) => self.raw[token.start..].trim_end(),
+      None => "",
     }
   }
+
+  /// Whether the argument with the given index starts with a quoted or
+  /// escaped part, in which case it is never treated as an option.
+  pub fn has_quoted_prefix(&self, index: usize) -> bool {
+    self.tokens.get(index).is_some_and(|t| t.quoted_prefix)
+  }
 }
 
 #[derive(Clone, Debug, PartialEq, PartialOrd, Ord, Eq)]
@@ -96,33 +192,105 @@ pub enum ReplyRequirement {
 pub struct ArgMetadata {
   pub name: String,
   pub description: String,
+  pub kind: ArgKind,
   pub requirement: ArgRequirement,
 }
 
 impl ArgMetadata {
-  pub fn new(name: String, description: String, requirement: ArgRequirement) -> Self {
+  pub fn new(
+    name: String,
+    description: String,
+    kind: ArgKind,
+    requirement: ArgRequirement,
+  ) -> Self {
     log::trace!(
-      "creating arg metadata: name='{}', description='{}', requirement={:?}",
+      "creating arg metadata: name='{}', description='{}', kind={:?}, requirement={:?}",
       name,
       description,
+      kind,
       requirement
     );
     Self {
       name,
       description,
+      kind,
       requirement,
     }
   }
 }
 
+/// A named option such as `--json`, `--level=admin` or `-l admin`.
+///
+/// Options without a value kind are boolean flags.
+#[derive(Clone, Debug, PartialEq, PartialOrd, Ord, Eq)]
+pub struct OptMetadata {
+  pub name: String,
+  pub short: Option<char>,
+  pub description: String,
+  pub kind: Option<ArgKind>,
+}
+
+impl OptMetadata {
+  pub fn new(
+    name: String,
+    short: Option<char>,
+    description: String,
+    kind: Option<ArgKind>,
+  ) -> Self {
+    log::trace!(
+      "creating opt metadata: name='{}', short={:?}, description='{}', kind={:?}",
+      name,
+      short,
+      description,
+      kind
+    );
+    Self {
+      name,
+      short,
+      description,
+      kind,
+    }
+  }
+
+  pub fn usage(&self) -> String {
+    let name = match self.short {
+      Some(short) => format!("-{}|--{}", short, self.name),
+      None => format!("--{}", self.name),
+    };
+
+    match &self.kind {
+      Some(kind) => format!("[{} <{}>]", name, kind.describe()),
+      None => format!("[{}]", name),
+    }
+  }
+}
+
 #[derive(Clone, Derivative)]
 #[derivative(Debug)]
 pub struct CommandMetadata {
   pub perm: Permission,
   pub desc: String,
+  pub aliases: Vec<String>,
+
+  /// Name of the owning plugin, set by the dispatcher on registration.
+  pub plugin: String,
+
+  /// Capability a role must carry to run the command. Defaults to
+  /// `<plugin>.<command>` on registration.
+  pub capability: String,
 
   pub reply: ReplyRequirement,
   pub args: Vec<ArgMetadata>,
+  pub opts: Vec<OptMetadata>,
+
+  /// Whether positional arguments beyond the declared ones are rejected.
+  /// Off by default, so commands keep ignoring trailing text.
+  pub strict: bool,
+
+  /// Whether the command acts on the whole bot rather than the chat it is
+  /// sent in. Such commands are checked against global permissions and
+  /// overrides only, so chat owners cannot run them.
+  pub global: bool,
 
   #[derivative(Debug = "ignore")]
   pub handler: handler::CommandHandler,
@@ -146,9 +314,83 @@ impl CommandMetadata {
     Self {
       perm,
       desc,
+      aliases: Vec::new(),
+      plugin: String::new(),
+      capability: String::new(),
       reply,
       args,
+      opts: Vec::new(),
+      strict: false,
+      global: false,
       handler,
     }
   }
+
+  pub fn with_aliases(
+    mut self,
+    aliases: &[&str],
+  ) -> Self {
+    self.aliases = aliases.iter().map(|a| a.to_string()).collect();
+    self
+  }
+
+  pub fn with_capability(
+    mut self,
+    capability: &str,
+  ) -> Self {
+    self.capability = capability.to_string();
+    self
+  }
+
+  pub fn with_opts(
+    mut self,
+    opts: Vec<OptMetadata>,
+  ) -> Self {
+    self.opts = opts;
+    self
+  }
+
+  pub fn with_strict_args(mut self) -> Self {
+    self.strict = true;
+    self
+  }
+
+  pub fn with_global_scope(mut self) -> Self {
+    self.global = true;
+    self
+  }
+
+  pub fn find_opt(&self, name: &str) -> Option<&OptMetadata> {
+    self.opts.iter().find(|opt| opt.name == name)
+  }
+
+  pub fn find_short_opt(&self, short: char) -> Option<&OptMetadata> {
+    self.opts.iter().find(|opt| opt.short == Some(short))
+  }
+
+  /// Builds a one-line usage synopsis, e.g. `/pmgrant <user_id> <perm>`.
+  pub fn usage(
+    &self,
+    prefix: impl std::fmt::Display,
+    name: &str,
+  ) -> String {
+    let mut parts = vec![format!("{}{}", prefix, name)];
+    parts.extend(self.opts.iter().map(|opt| opt.usage()));
+
+    for arg in &self.args {
+      let rest = if arg.kind == ArgKind::Rest { "..." } else { "" };
+      parts.push(match arg.requirement {
+        ArgRequirement::Required => format!("<{}{}>", arg.name, rest),
+        ArgRequirement::Optional => format!("[{}{}]", arg.name, rest),
+        ArgRequirement::OnlyWithoutReply => format!("<{}{}|reply>", arg.name, rest),
+        ArgRequirement::OnlyWithReply => format!("(reply) <{}{}>", arg.name, rest),
+      });
+    }
+
+    if self.reply == ReplyRequirement::Required {
+      parts.push("(reply)".to_string());
+    }
+
+    parts.join(" ")
+  }
 }
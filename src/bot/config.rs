@@ -1,39 +1,77 @@
 use std::sync::Arc;
-use tokio::sync::Mutex;
+use tokio::sync::RwLock;
 
 use serde::{Deserialize, Serialize};
 
+use super::command::Prefix;
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct Config {
   pub token: String,
-  pub prefixes: Vec<char>,
+  pub prefixes: Vec<Prefix>,
+  pub case_insensitive: bool,
+
+  /// The bot's own username, known once logged in.
+  pub username: Option<String>,
 }
 
-impl Config {
-  pub fn default() -> Self {
+impl Default for Config {
+  fn default() -> Self {
     Self {
       token: String::new(),
-      prefixes: vec!['/'],
+      prefixes: vec![Prefix::Literal("/".to_string())],
+      case_insensitive: false,
+      username: None,
     }
   }
+}
 
-  pub fn default_arc_mutex() -> Arc<Mutex<Self>> {
-    Arc::new(Mutex::new(Self::default()))
+impl Config {
+  pub fn default_shared() -> Arc<RwLock<Self>> {
+    Arc::new(RwLock::new(Self::default()))
   }
 
-  pub fn new(token: String, prefixes: Vec<char>) -> Self {
-    Self { token, prefixes }
+  pub fn new(
+    token: String,
+    prefixes: Vec<Prefix>,
+    case_insensitive: bool,
+  ) -> Self {
+    Self {
+      token,
+      prefixes,
+      case_insensitive,
+      username: None,
+    }
   }
 
-  pub fn new_shared(token: String, prefixes: Vec<char>) -> Arc<Mutex<Self>> {
-    Arc::new(Mutex::new(Self::new(token, prefixes)))
+  pub fn new_shared(
+    token: String,
+    prefixes: Vec<Prefix>,
+    case_insensitive: bool,
+  ) -> Arc<RwLock<Self>> {
+    Arc::new(RwLock::new(Self::new(token, prefixes, case_insensitive)))
   }
 
   pub fn get_token(&self) -> &str {
     &self.token
   }
 
-  pub fn get_prefixes(&self) -> Vec<char> {
+  pub fn get_prefixes(&self) -> Vec<Prefix> {
     self.prefixes.clone()
   }
+
+  pub fn is_case_insensitive(&self) -> bool {
+    self.case_insensitive
+  }
+
+  pub fn get_username(&self) -> Option<&str> {
+    self.username.as_deref()
+  }
+
+  pub fn set_username(
+    &mut self,
+    username: Option<String>,
+  ) {
+    self.username = username;
+  }
 }
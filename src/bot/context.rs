@@ -1,6 +1,6 @@
 use derivative::Derivative;
 use std::sync::Arc;
-use tokio::sync::Mutex;
+use tokio::sync::{Mutex, RwLock};
 
 use r2d2::Pool;
 use r2d2_sqlite::SqliteConnectionManager;
@@ -8,40 +8,138 @@ use r2d2_sqlite::SqliteConnectionManager;
 use crate::utils::style::{self, DynStyle};
 
 use super::config::Config;
-use super::dispatcher::Dispatcher;
+use super::dispatcher::SharedDispatcher;
+use super::shutdown::Shutdown;
 
+use crate::audit::manager::AuditLog;
+use crate::permissions::admins::AdminResolver;
+use crate::permissions::blocklist::BlockList;
 use crate::permissions::manager::PermissionManager;
+use crate::permissions::roles::RoleManager;
+use crate::settings::manager::SettingsManager;
 
-#[derive(Derivative)]
+use super::command::Prefix;
+use crate::permissions::types::{Permission, Scope};
+
+/// Every field is shared, so cloning gives a snapshot that can be used
+/// without holding the context mutex.
+#[derive(Clone, Derivative)]
 #[derivative(Debug)]
 pub struct Context {
-  pub cfg: Arc<Mutex<Config>>,
+  pub cfg: Arc<RwLock<Config>>,
   pub db: Arc<Pool<SqliteConnectionManager>>,
-  pub perm_mgr: Arc<Mutex<PermissionManager>>,
+  pub perm_mgr: Arc<PermissionManager>,
+  pub admins: Arc<AdminResolver>,
+  pub roles: Arc<RoleManager>,
+  pub blocklist: Arc<BlockList>,
+  pub audit: Arc<AuditLog>,
+  pub settings: Arc<SettingsManager>,
   pub bot: Arc<teloxide::Bot>,
 
-  pub dp: Arc<tokio::sync::Mutex<Dispatcher>>,
+  pub dp: SharedDispatcher,
+  pub shutdown: Arc<Shutdown>,
 
   #[derivative(Debug = "ignore")]
   pub style: Arc<dyn style::DynStyle>,
 }
 
 impl Context {
+  #[allow(clippy::too_many_arguments)]
   pub fn new(
-    cfg: Arc<Mutex<Config>>,
+    cfg: Arc<RwLock<Config>>,
     db: Arc<Pool<SqliteConnectionManager>>,
-    perm_mgr: Arc<Mutex<PermissionManager>>,
+    perm_mgr: Arc<PermissionManager>,
+    admins: Arc<AdminResolver>,
+    roles: Arc<RoleManager>,
+    blocklist: Arc<BlockList>,
+    audit: Arc<AuditLog>,
+    settings: Arc<SettingsManager>,
     bot: Arc<teloxide::Bot>,
-    dp: Arc<tokio::sync::Mutex<Dispatcher>>,
+    dp: SharedDispatcher,
+    shutdown: Arc<Shutdown>,
     style: Arc<dyn DynStyle>,
   ) -> Self {
     Self {
       cfg,
       db,
       perm_mgr,
+      admins,
+      roles,
+      blocklist,
+      audit,
+      settings,
       bot,
       dp,
+      shutdown,
       style,
     }
   }
+
+  /// Permission of a user in a chat, raised to what their Telegram role
+  /// grants there when the chat maps administrators.
+  ///
+  /// Takes the context mutex rather than `&self` so the lock is not held
+  /// while the administrator list is fetched.
+  pub async fn permission_in(
+    ctx: &Mutex<Context>,
+    chat_id: teloxide::types::ChatId,
+    user_id: teloxide::types::UserId,
+  ) -> anyhow::Result<Permission> {
+    let (perm_mgr, settings, admins, bot) = {
+      let ctx = ctx.lock().await;
+      (
+        ctx.perm_mgr.clone(),
+        ctx.settings.clone(),
+        ctx.admins.clone(),
+        ctx.bot.clone(),
+      )
+    };
+
+    let perm = perm_mgr.get(Scope::Chat(chat_id), user_id)?;
+
+    if !settings.get_admin_mapping(chat_id)? {
+      return Ok(perm);
+    }
+
+    match admins.lookup(&bot, chat_id, user_id).await {
+      Ok(Some(mapped)) if mapped.level() > perm.level() => Ok(mapped),
+      Ok(_) => Ok(perm),
+      Err(err) => {
+        log::warn!("failed to fetch administrators of chat {}: {:?}", chat_id, err);
+        Ok(perm)
+      }
+    }
+  }
+
+  /// Prefixes in effect for a chat: its own if set, the global ones otherwise.
+  pub async fn prefixes_for(&self, chat_id: teloxide::types::ChatId) -> Vec<Prefix> {
+    Self::prefixes_in(&self.settings, &self.cfg, chat_id).await
+  }
+
+  /// Same as [`Context::prefixes_for`], for callers that took the settings
+  /// and config out of the context so its mutex is not held while querying.
+  pub async fn prefixes_in(
+    settings: &SettingsManager,
+    cfg: &RwLock<Config>,
+    chat_id: teloxide::types::ChatId,
+  ) -> Vec<Prefix> {
+    match settings.get_prefixes(chat_id) {
+      Ok(Some(prefixes)) if !prefixes.is_empty() => prefixes,
+      Ok(_) => cfg.read().await.get_prefixes(),
+      Err(err) => {
+        log::error!("failed to read prefixes for chat {}: {:?}", chat_id, err);
+        cfg.read().await.get_prefixes()
+      }
+    }
+  }
+
+  /// The chat's primary prefix, rendered for help and usage messages.
+  pub async fn display_prefix_for(&self, chat_id: teloxide::types::ChatId) -> String {
+    let prefixes = self.prefixes_for(chat_id).await;
+    let cfg = self.cfg.read().await;
+    prefixes
+      .first()
+      .map(|prefix| prefix.display(cfg.get_username()))
+      .unwrap_or_default()
+  }
 }
@@ -1,23 +1,55 @@
 use std::sync::{Arc, Weak};
-use tokio::sync::Mutex;
+use tokio::sync::{Mutex, RwLock};
 
 use derivative::Derivative;
 use indexmap::IndexMap;
 
+use teloxide::payloads::SendMessageSetters;
+use teloxide::prelude::Requester;
+use teloxide::types::ParseMode;
+use teloxide::utils::html;
+
+use crate::audit::manager::AuditEntry;
+use crate::error;
+use crate::permissions::blocklist::{BlockList, BlockTarget};
+use crate::permissions::types::{CommandOverride, Permission, Scope};
+use crate::utils::style;
+
+use super::args;
 use super::command;
 use super::context;
 use super::handler;
 use super::plugin;
+use super::tasks;
+
+/// The dispatcher shared between the update loop and handlers.
+///
+/// Readers clone the current `Arc` through [`current`] and release the lock
+/// at once, so updates never wait on each other. Changes go through
+/// [`Arc::make_mut`] under the write lock, which copies the dispatcher when
+/// readers still hold the previous one.
+pub type SharedDispatcher = Arc<RwLock<Arc<Dispatcher>>>;
+
+/// The dispatcher as of now, for handling one update or command.
+pub async fn current(dp: &SharedDispatcher) -> Arc<Dispatcher> {
+  dp.read().await.clone()
+}
 
-#[derive(Derivative)]
+#[derive(Clone, Derivative)]
 #[derivative(Debug)]
 pub struct Dispatcher {
   pub context: Weak<Mutex<context::Context>>,
 
   pub command_handlers: IndexMap<String, command::CommandMetadata>,
 
+  /// Maps every alias to the name of the command it stands for.
+  pub aliases: IndexMap<String, String>,
+
+  pub tasks: Arc<tasks::TaskTracker>,
+
+  /// Update handlers, each with the name of the plugin it belongs to.
   #[derivative(Debug = "ignore")]
-  pub update_handlers: Vec<handler::UpdateHandler>,
+  pub update_handlers: Vec<(String, handler::UpdateHandler)>,
 
   #[derivative(Debug = "ignore")]
   pub plugins: plugin::PluginMap,
@@ -28,28 +60,82 @@ impl Dispatcher {
     Self {
       context,
       command_handlers: IndexMap::new(),
+      aliases: IndexMap::new(),
+      tasks: tasks::TaskTracker::new_shared(),
       update_handlers: Vec::new(),
       plugins: IndexMap::new(),
     }
   }
 
-  pub fn new_shared(
-    context: Weak<Mutex<super::context::Context>>
-  ) -> Arc<tokio::sync::Mutex<Self>> {
-    Arc::new(tokio::sync::Mutex::new(Self::new(context)))
+  pub fn new_shared(context: Weak<Mutex<super::context::Context>>) -> SharedDispatcher {
+    Arc::new(RwLock::new(Arc::new(Self::new(context))))
+  }
+
+  /// Checks every name a plugin wants to claim before anything is
+  /// registered, so a rejected plugin leaves the dispatcher untouched.
+  fn check_collisions(
+    &self,
+    plugin_name: &str,
+    commands: &IndexMap<String, command::CommandMetadata>,
+  ) -> Result<(), error::Error> {
+    if self.plugins.contains_key(plugin_name) {
+      return Err(error::Error::PluginAlreadyRegistered(plugin_name.to_string()));
+    }
+
+    let mut claimed: Vec<&str> = Vec::new();
+
+    for (cmd_name, meta) in commands {
+      for name in std::iter::once(cmd_name).chain(meta.aliases.iter()) {
+        if name.contains('.') || name.is_empty() {
+          return Err(error::Error::InvalidCommandName(name.clone()));
+        }
+
+        let taken = self
+          .resolve_name(name, true)
+          .map(|owner| owner.to_string())
+          .or_else(|| {
+            claimed
+              .iter()
+              .any(|other| other.eq_ignore_ascii_case(name))
+              .then(|| name.clone())
+          });
+
+        if let Some(existing) = taken {
+          let owner = self
+            .command_handlers
+            .get(&existing)
+            .map(|meta| meta.plugin.clone())
+            .unwrap_or_else(|| plugin_name.to_string());
+
+          return Err(error::Error::CommandCollision {
+            name: name.clone(),
+            plugin: plugin_name.to_string(),
+            existing,
+            owner,
+          });
+        }
+
+        claimed.push(name);
+      }
+    }
+
+    Ok(())
   }
 
   pub async fn register_plugin(
     &mut self,
-    plugin: plugin::PluginBox,
-  ) {
+    plugin: Arc<dyn plugin::Plugin>,
+  ) -> anyhow::Result<()> {
     let plugin_name = plugin.name().to_string();
+    let commands = plugin.commands();
+
+    self.check_collisions(&plugin_name, &commands)?;
 
     for update in plugin.update_handlers() {
-      self.update_handlers.push(update);
+      self.update_handlers.push((plugin_name.clone(), update));
     }
 
-    for (cmd_name, meta) in plugin.commands() {
+    for (cmd_name, mut meta) in commands {
       log::debug!(
         "registering '{}' ({:?}) from plugin '{}'",
         cmd_name,
@@ -57,10 +143,135 @@ impl Dispatcher {
         plugin_name
       );
 
+      for alias in &meta.aliases {
+        self.aliases.insert(alias.clone(), cmd_name.clone());
+      }
+
+      meta.plugin = plugin_name.clone();
+      if meta.capability.is_empty() {
+        meta.capability = format!("{}.{}", plugin_name, cmd_name);
+      }
       self.command_handlers.insert(cmd_name, meta);
     }
 
-    self.plugins.insert(plugin_name.clone(), plugin);
+    log::info!(
+      "plugin '{}' owns commands: {}",
+      plugin_name,
+      self
+        .command_handlers
+        .iter()
+        .filter(|(_, meta)| meta.plugin == plugin_name)
+        .map(|(name, _)| name.as_str())
+        .collect::<Vec<_>>()
+        .join(", ")
+    );
+
+    self.plugins.insert(plugin_name, plugin);
+
+    Ok(())
+  }
+
+  fn resolve_name(
+    &self,
+    name: &str,
+    case_insensitive: bool,
+  ) -> Option<&str> {
+    // The fully-qualified `plugin.command` form is always available and
+    // bypasses any ambiguity between plugins.
+    if let Some((plugin_name, short)) = name.split_once('.') {
+      let resolved = self.resolve_name(short, case_insensitive)?;
+      let owner = &self.command_handlers.get(resolved)?.plugin;
+
+      let matches = if case_insensitive {
+        owner.eq_ignore_ascii_case(plugin_name)
+      } else {
+        owner == plugin_name
+      };
+
+      return matches.then_some(resolved);
+    }
+
+    if let Some((key, _)) = self.command_handlers.get_key_value(name) {
+      return Some(key);
+    }
+
+    if let Some(target) = self.aliases.get(name) {
+      return Some(target);
+    }
+
+    if !case_insensitive {
+      return None;
+    }
+
+    let folded = name.to_lowercase();
+    self
+      .command_handlers
+      .keys()
+      .find(|key| key.to_lowercase() == folded)
+      .or_else(|| {
+        self
+          .aliases
+          .iter()
+          .find(|(alias, _)| alias.to_lowercase() == folded)
+          .map(|(_, target)| target)
+      })
+      .map(|name| name.as_str())
+  }
+
+  /// Looks up a command by name or alias, returning its canonical name and
+  /// metadata.
+  pub fn resolve(
+    &self,
+    name: &str,
+    case_insensitive: bool,
+  ) -> Option<(&str, &command::CommandMetadata)> {
+    let name = self.resolve_name(name, case_insensitive)?;
+    self
+      .command_handlers
+      .get_key_value(name)
+      .map(|(key, meta)| (key.as_str(), meta))
+  }
+
+  /// Whether a plugin is enabled in the scope an update or command came
+  /// from.
+  pub async fn is_plugin_enabled(
+    &self,
+    scope: Scope,
+    plugin: &str,
+  ) -> anyhow::Result<bool> {
+    let settings = match self.context.upgrade() {
+      Some(ctx) => ctx.lock().await.settings.clone(),
+      None => return Err(error::Error::ContextDisposed.into()),
+    };
+
+    let enabled = settings.is_plugin_enabled(scope, plugin)?;
+    Ok(enabled)
+  }
+
+  async fn reply_usage(
+    &self,
+    bot: &teloxide::Bot,
+    msg: &teloxide::prelude::Message,
+    usage: String,
+    reason: impl std::fmt::Display,
+  ) -> anyhow::Result<()> {
+    let style = style::get_style(self.context.clone()).await;
+
+    bot
+      .send_message(
+        msg.chat.id,
+        format!(
+          "{} <b>{}</b>\n{} Usage: <code>{}</code>",
+          style.err(),
+          html::escape(&reason.to_string()),
+          style.info(),
+          html::escape(&usage)
+        ),
+      )
+      .parse_mode(ParseMode::Html)
+      .await?;
+
+    Ok(())
   }
 
   pub async fn handle_command(
@@ -77,25 +288,122 @@ impl Dispatcher {
       }
     };
 
-    if let Some(info) = self.command_handlers.get(&cmd.name) {
-      if let Some(ctx) = self.context.upgrade() {
-        let ctx = ctx.lock().await;
-        let cfg = ctx.cfg.lock().await;
-        let pm = ctx.perm_mgr.lock().await;
-
-        if pm.can(user_id, info.perm)? {
-          drop(cfg);
-          log::trace!("executing command {} for user {}", cmd.name, user_id);
-          (info.handler)(bot.clone(), msg.clone(), cmd, self.context.clone());
-        } else {
-          log::trace!(
-            "user {} does not have permission for command {}",
-            user_id,
-            cmd.name
-          );
+    let ctx = match self.context.upgrade() {
+      Some(ctx) => ctx,
+      None => {
+        log::warn!("cannot execute command {}: context dropped", cmd.name);
+        return Ok(());
+      }
+    };
+
+    // Take what is needed in one go, so the context is not held while the
+    // managers are.
+    let (cfg, perm_mgr, roles, audit) = {
+      let ctx = ctx.lock().await;
+      (
+        ctx.cfg.clone(),
+        ctx.perm_mgr.clone(),
+        ctx.roles.clone(),
+        ctx.audit.clone(),
+      )
+    };
+
+    let case_insensitive = cfg.read().await.is_case_insensitive();
+
+    if let Some((name, info)) = self.resolve(&cmd.name, case_insensitive) {
+      if !self.is_plugin_enabled(Scope::Chat(msg.chat.id), &info.plugin).await? {
+        log::trace!(
+          "command {} ignored: plugin {} is disabled in chat {}",
+          name,
+          info.plugin,
+          msg.chat.id
+        );
+        return Ok(());
+      }
+
+      let scope = if info.global {
+        Scope::Global
+      } else {
+        Scope::Chat(msg.chat.id)
+      };
+
+      let ovr = perm_mgr.command_override(scope, name)?;
+
+      let required = match ovr {
+        Some(CommandOverride::Require(perm)) => perm,
+        Some(CommandOverride::Disabled) => {
+          log::trace!("command {} is disabled in chat {}", name, msg.chat.id);
+          return Ok(());
         }
+        None => info.perm,
+      };
+
+      let perm = match scope {
+        Scope::Global => perm_mgr.get(Scope::Global, user_id)?,
+        Scope::Chat(chat_id) => context::Context::permission_in(&ctx, chat_id, user_id).await?,
+      };
+
+      let allowed = roles.grants(scope, user_id, perm, &info.capability, required)?;
+
+      if allowed {
+        let mut cmd = cmd;
+        cmd.name = name.to_string();
+
+        // Messages in forum topics reply to the topic's opening message,
+        // which is not a reply the user made.
+        let reply = msg
+          .reply_to_message()
+          .filter(|reply| reply.forum_topic_created().is_none());
+
+        cmd.values = match args::parse(info, &cmd, reply).await {
+          Ok(values) => values,
+          Err(err) => {
+            log::trace!("invalid usage of command {}: {}", cmd.name, err);
+            self
+              .reply_usage(&bot, &msg, info.usage(&cmd.prefix, &cmd.name), err)
+              .await?;
+            return Ok(());
+          }
+        };
+
+        log::trace!("executing command {} for user {}", cmd.name, user_id);
+
+        // Lowering a command's level with an override must not hide it
+        // from the audit log.
+        if info.perm.level().max(required.level()) >= Permission::OWNER.level() {
+          let entry = AuditEntry {
+            chat_id: Some(msg.chat.id),
+            actor: Some(user_id),
+            action: "command".to_string(),
+            command: Some(cmd.name.clone()),
+            detail: Some(cmd.raw.trim().to_string()).filter(|raw| !raw.is_empty()),
+            ..Default::default()
+          };
+
+          if let Err(err) = audit.record(entry) {
+            log::error!("failed to audit command {}: {:?}", cmd.name, err);
+          }
+        }
+
+        let name = cmd.name.clone();
+        let chat_id = msg.chat.id;
+        let fut = (info.handler)(bot.clone(), msg.clone(), cmd, self.context.clone());
+
+        self
+          .tasks
+          .spawn(name.clone(), chat_id, user_id, async move {
+            if let Err(err) = fut.await {
+              log::error!("command {} failed: {:?}", name, err);
+              error::emit(Some(bot), Some(msg), err).await;
+            }
+          })
+          .await;
       } else {
-        log::warn!("cannot execute command {}: context dropped", cmd.name);
+        log::trace!(
+          "user {} does not have permission for command {}",
+          user_id,
+          cmd.name
+        );
       }
     } else {
       log::trace!(
@@ -108,33 +416,81 @@ impl Dispatcher {
     Ok(())
   }
 
+  fn is_addressed_to(
+    target: &Option<String>,
+    username: &Option<String>,
+  ) -> bool {
+    match (target, username) {
+      (Some(target), Some(username)) => target.eq_ignore_ascii_case(username),
+      _ => true,
+    }
+  }
+
   pub async fn handle_message(
     &self,
     bot: teloxide::Bot,
     msg: teloxide::prelude::Message,
   ) -> anyhow::Result<()> {
     if let Some(text) = msg.text().or(msg.caption()) {
-      let prefixes = if let Some(ctx) = self.context.upgrade() {
-        let ctx = ctx.lock().await;
-        let cfg = ctx.cfg.lock().await;
-        cfg.get_prefixes()
-      } else {
-        log::warn!("cannot handle message: context already destroyed");
-        return Ok(());
+      let (settings, cfg) = match self.context.upgrade() {
+        Some(ctx) => {
+          let ctx = ctx.lock().await;
+          (ctx.settings.clone(), ctx.cfg.clone())
+        }
+        None => {
+          log::warn!("cannot handle message: context already destroyed");
+          return Ok(());
+        }
       };
 
-      if let Some(cmd) = command::Command::with_prefixes(text, prefixes) {
-        log::trace!(
-          "handling message as command {} from user {:?}",
-          cmd.name,
-          msg.from.as_ref().map(|u| u.id)
-        );
+      let prefixes = context::Context::prefixes_in(&settings, &cfg, msg.chat.id).await;
+      let (username, case_insensitive) = {
+        let cfg = cfg.read().await;
+        (cfg.get_username().map(str::to_string), cfg.is_case_insensitive())
+      };
+
+      match command::Command::with_prefixes(text, &prefixes, username.as_deref()) {
+        Ok(Some(cmd)) => {
+          if !Self::is_addressed_to(&cmd.target, &username) {
+            log::trace!(
+              "command {} is addressed to {:?}, not to us, ignoring",
+              cmd.name,
+              cmd.target
+            );
+            return Ok(());
+          }
+
+          log::trace!(
+            "handling message as command {} from user {:?}",
+            cmd.name,
+            msg.from.as_ref().map(|u| u.id)
+          );
 
-        if let Err(e) = self.handle_command(bot, msg, cmd).await {
-          log::error!("failed to handle command: {:?}", e);
+          if let Err(e) = self.handle_command(bot, msg, cmd).await {
+            log::error!("failed to handle command: {:?}", e);
+          }
+        }
+        Ok(None) => {
+          log::trace!("message does not match any command, ignoring");
+        }
+        Err(err) => {
+          if !Self::is_addressed_to(&err.target, &username) {
+            return Ok(());
+          }
+
+          // Only complain about commands we actually know, so malformed
+          // messages meant for other bots stay unanswered.
+          if let Some((name, info)) = self.resolve(&err.name, case_insensitive)
+            && self
+              .is_plugin_enabled(Scope::Chat(msg.chat.id), &info.plugin)
+              .await?
+          {
+            log::trace!("failed to parse command {}: {}", name, err);
+            self
+              .reply_usage(&bot, &msg, info.usage(&err.prefix, name), err)
+              .await?;
+          }
         }
-      } else {
-        log::trace!("message does not match any command, ignoring");
       }
     } else {
       log::trace!("message has no text, ignoring");
@@ -143,17 +499,73 @@ impl Dispatcher {
     Ok(())
   }
 
+  /// Whether the update's sender or chat is on the blocklist.
+  fn is_blocked(
+    blocklist: &BlockList,
+    update: &teloxide::prelude::Update,
+  ) -> anyhow::Result<bool> {
+    if let Some(user) = update.from()
+      && blocklist.is_blocked(BlockTarget::User(user.id))?
+    {
+      return Ok(true);
+    }
+
+    if let Some(chat) = update.chat()
+      && blocklist.is_blocked(BlockTarget::Chat(chat.id))?
+    {
+      return Ok(true);
+    }
+
+    Ok(false)
+  }
+
   pub async fn handle_update(
     &self,
     bot: teloxide::Bot,
     update: teloxide::prelude::Update,
   ) -> anyhow::Result<()> {
-    for handler in &self.update_handlers {
-      if self.context.upgrade().is_some() {
-        (handler)(bot.clone(), update.clone(), self.context.clone()).await;
-      } else {
-        anyhow::bail!("cannot handle message: context already destroyed");
+    let (blocklist, settings, admins) = match self.context.upgrade() {
+      Some(ctx) => {
+        let ctx = ctx.lock().await;
+        (ctx.blocklist.clone(), ctx.settings.clone(), ctx.admins.clone())
       }
+      None => anyhow::bail!("cannot handle update: context already destroyed"),
+    };
+
+    if Self::is_blocked(&blocklist, &update)? {
+      log::trace!("dropping update {} from a blocked user or chat", update.id.0);
+      return Ok(());
+    }
+
+    let scope = update
+      .chat()
+      .map_or(Scope::Global, |chat| Scope::Chat(chat.id));
+
+    // Looked up once per plugin rather than once per update handler.
+    let mut plugin_states: IndexMap<&str, bool> = IndexMap::new();
+
+    for (plugin_name, handler) in &self.update_handlers {
+      let enabled = match plugin_states.get(plugin_name.as_str()) {
+        Some(&enabled) => enabled,
+        None => {
+          let enabled = settings.is_plugin_enabled(scope, plugin_name)?;
+          plugin_states.insert(plugin_name, enabled);
+          enabled
+        }
+      };
+
+      if !enabled {
+        log::trace!("skipping update handler of plugin {} disabled in {}", plugin_name, scope);
+        continue;
+      }
+
+      (handler)(bot.clone(), update.clone(), self.context.clone()).await;
+    }
+
+    if let teloxide::types::UpdateKind::ChatMember(member)
+    | teloxide::types::UpdateKind::MyChatMember(member) = &update.kind
+    {
+      admins.invalidate(member.chat.id);
     }
 
     if let teloxide::types::UpdateKind::Message(msg) = update.kind {
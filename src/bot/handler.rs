@@ -9,8 +9,18 @@ pub type MessageHandler = Arc<
   dyn Fn(teloxide::Bot, teloxide::prelude::Message, Weak<Mutex<context::Context>>) + Send + Sync,
 >;
 
+pub type CommandFuture = Pin<Box<dyn Future<Output = anyhow::Result<()>> + Send>>;
+
+/// Future returned by plugin lifecycle hooks.
+pub type HookFuture<'a> = Pin<Box<dyn Future<Output = anyhow::Result<()>> + Send + 'a>>;
+
 pub type CommandHandler = Arc<
-  dyn Fn(teloxide::Bot, teloxide::prelude::Message, command::Command, Weak<Mutex<context::Context>>)
+  dyn Fn(
+      teloxide::Bot,
+      teloxide::prelude::Message,
+      command::Command,
+      Weak<Mutex<context::Context>>,
+    ) -> CommandFuture
     + Send
     + Sync,
 >;
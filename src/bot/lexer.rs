@@ -0,0 +1,170 @@
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+pub enum LexError {
+  #[error("unterminated {0} quote")]
+  UnterminatedQuote(char),
+
+  #[error("dangling escape at end of input")]
+  DanglingEscape,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Token {
+  pub value: String,
+
+  /// Byte offset of the token's first character in the lexed input.
+  pub start: usize,
+
+  /// Whether any part of the token was quoted or escaped.
+  pub quoted: bool,
+
+  /// Whether the token starts with a quoted or escaped part, so `"--x"` and
+  /// `\-5` can be told apart from `--x="a b"`.
+  pub quoted_prefix: bool,
+}
+
+/// Splits input into shell-style words.
+///
+/// Any whitespace separates words. Double quotes group text and honour
+/// backslash escapes, single quotes group text literally, and a backslash
+/// outside quotes escapes the next character. Quoted and unquoted parts that
+/// touch are joined into a single word, and `""` yields an empty word.
+pub fn tokenize(s: &str) -> Result<Vec<Token>, LexError> {
+  let mut tokens = Vec::new();
+  let mut chars = s.char_indices().peekable();
+
+  let mut current: Option<Token> = None;
+  let mut quote: Option<char> = None;
+
+  while let Some((idx, c)) = chars.next() {
+    let token = current.get_or_insert_with(|| Token {
+      value: String::new(),
+      start: idx,
+      quoted: false,
+      quoted_prefix: false,
+    });
+
+    match (quote, c) {
+      (Some('\''), '\'') | (Some('"'), '"') => {
+        quote = None;
+      }
+      (Some('\''), _) => token.value.push(c),
+      (_, '\\') => match chars.next() {
+        Some((_, escaped)) => {
+          token.quoted_prefix |= !token.quoted && token.value.is_empty();
+          token.value.push(escaped);
+          token.quoted = true;
+        }
+        None => return Err(LexError::DanglingEscape),
+      },
+      (Some(_), _) => token.value.push(c),
+      (None, '"') | (None, '\'') => {
+        token.quoted_prefix |= !token.quoted && token.value.is_empty();
+        quote = Some(c);
+        token.quoted = true;
+      }
+      (None, _) if c.is_whitespace() => {
+        if let Some(token) = current.take()
+          && (token.quoted || !token.value.is_empty())
+        {
+          tokens.push(token);
+        }
+      }
+      (None, _) => token.value.push(c),
+    }
+  }
+
+  if let Some(q) = quote {
+    return Err(LexError::UnterminatedQuote(q));
+  }
+
+  if let Some(token) = current
+    && (token.quoted || !token.value.is_empty())
+  {
+    tokens.push(token);
+  }
+
+  log::trace!("tokenized '{}' into {} tokens", s, tokens.len());
+
+  Ok(tokens)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn values(s: &str) -> Vec<String> {
+    tokenize(s).unwrap().into_iter().map(|t| t.value).collect()
+  }
+
+  #[test]
+  fn splits_on_any_whitespace() {
+    assert_eq!(values("  a  b\tc\nd "), ["a", "b", "c", "d"]);
+    assert!(tokenize("   ").unwrap().is_empty());
+  }
+
+  #[test]
+  fn records_token_offsets() {
+    let tokens = tokenize("  foo \"bar baz\"").unwrap();
+    assert_eq!(tokens[0].start, 2);
+    assert_eq!(tokens[1].start, 6);
+  }
+
+  #[test]
+  fn double_quotes_group_and_honour_escapes() {
+    assert_eq!(
+      values(r#""two words" "say \"hi\"" "a\\b""#),
+      ["two words", "say \"hi\"", "a\\b"]
+    );
+  }
+
+  #[test]
+  fn single_quotes_are_literal() {
+    assert_eq!(values(r#"'a\b "c"'"#), [r#"a\b "c""#]);
+  }
+
+  #[test]
+  fn backslash_escapes_outside_quotes() {
+    assert_eq!(values(r"a\ b c\\d"), ["a b", r"c\d"]);
+  }
+
+  #[test]
+  fn adjacent_parts_join_into_one_word() {
+    assert_eq!(values(r#"--reason="two words" x'y'z"#), ["--reason=two words", "xyz"]);
+  }
+
+  #[test]
+  fn empty_quotes_yield_an_empty_word() {
+    assert_eq!(values(r#""" x ''"#), ["", "x", ""]);
+  }
+
+  #[test]
+  fn marks_quoted_and_escaped_tokens() {
+    let quoted: Vec<bool> = tokenize(r#"plain "quoted" \-escaped"#)
+      .unwrap()
+      .into_iter()
+      .map(|t| t.quoted)
+      .collect();
+    assert_eq!(quoted, [false, true, true]);
+  }
+
+  #[test]
+  fn marks_quoted_prefixes() {
+    let prefixes: Vec<bool> = tokenize(r#"--k="a b" "--k" \-5 -5 ''-x"#)
+      .unwrap()
+      .into_iter()
+      .map(|t| t.quoted_prefix)
+      .collect();
+    assert_eq!(prefixes, [false, true, true, false, true]);
+  }
+
+  #[test]
+  fn rejects_unterminated_quotes() {
+    assert_eq!(tokenize("\"abc"), Err(LexError::UnterminatedQuote('"')));
+    assert_eq!(tokenize("a 'b"), Err(LexError::UnterminatedQuote('\'')));
+  }
+
+  #[test]
+  fn rejects_dangling_escape() {
+    assert_eq!(tokenize(r"abc\"), Err(LexError::DanglingEscape));
+  }
+}
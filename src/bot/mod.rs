@@ -1,6 +1,10 @@
+pub mod args;
 pub mod command;
 pub mod config;
 pub mod context;
 pub mod dispatcher;
 pub mod handler;
+pub mod lexer;
 pub mod plugin;
+pub mod shutdown;
+pub mod tasks;
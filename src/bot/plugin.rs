@@ -1,30 +1,101 @@
-use std::sync::Arc;
+use std::sync::{Arc, Weak};
 
 use indexmap::IndexMap;
 use tokio::sync::Mutex;
 
-use super::dispatcher::Dispatcher;
+use super::dispatcher::{self, SharedDispatcher};
 
 use super::command;
+use super::context::Context;
 use super::handler;
 
 pub type PluginBox = Box<dyn Plugin>;
-pub type PluginMap = IndexMap<String, PluginBox>;
+pub type PluginMap = IndexMap<String, Arc<dyn Plugin>>;
 
 pub trait Plugin: Send + Sync {
   fn name(&self) -> &str;
   fn commands(&self) -> IndexMap<String, command::CommandMetadata>;
   fn update_handlers(&self) -> Vec<handler::UpdateHandler>;
+
+  /// Called before the plugin is registered, e.g. to create its tables. A
+  /// failure keeps the plugin from being registered.
+  fn on_load<'a>(
+    &'a self,
+    _ctx: &'a Context,
+  ) -> handler::HookFuture<'a> {
+    Box::pin(async { Ok(()) })
+  }
+
+  /// Called once every plugin is registered. Takes the context by `Weak`
+  /// so background jobs spawned here can keep it.
+  fn on_start(
+    &self,
+    _ctx: Weak<Mutex<Context>>,
+  ) -> handler::HookFuture<'static> {
+    Box::pin(async { Ok(()) })
+  }
+
+  /// Called on shutdown, after running commands have finished or been
+  /// aborted, and when registration fails after a successful load.
+  fn on_stop<'a>(
+    &'a self,
+    _ctx: &'a Context,
+  ) -> handler::HookFuture<'a> {
+    Box::pin(async { Ok(()) })
+  }
 }
 
+/// Loads and registers every plugin, then starts the ones that made it. A
+/// plugin failing any step is reported and skipped; the others carry on.
 pub async fn register_all(
-  dp: Arc<Mutex<Dispatcher>>,
+  dp: SharedDispatcher,
   plugs: Vec<PluginBox>,
 ) {
+  let ctx = match dispatcher::current(&dp).await.context.upgrade() {
+    Some(ctx) => ctx,
+    None => {
+      log::error!("cannot register plugins: context dropped");
+      return;
+    }
+  };
+
+  // Hooks get a snapshot, so none of them runs under the context mutex.
+  let snapshot = ctx.lock().await.clone();
+  let mut registered = Vec::new();
+
   for plug in plugs {
     let name = plug.name().to_string();
+
+    log::debug!("loading plugin {}", name);
+    if let Err(err) = plug.on_load(&snapshot).await {
+      log::error!("failed to load plugin {}: {:?}", name, err);
+      continue;
+    }
+
     log::debug!("registering plugin {}", name);
-    dp.lock().await.register_plugin(plug).await;
-    log::debug!("plugin {} successfully registered", name);
+    let plug: Arc<dyn Plugin> = Arc::from(plug);
+    let result = {
+      let mut current = dp.write().await;
+      Arc::make_mut(&mut current).register_plugin(plug.clone()).await
+    };
+
+    match result {
+      Ok(()) => {
+        log::debug!("plugin {} successfully registered", name);
+        registered.push(plug);
+      }
+      Err(err) => {
+        log::error!("failed to register plugin {}: {}", name, err);
+        if let Err(err) = plug.on_stop(&snapshot).await {
+          log::error!("failed to stop plugin {}: {:?}", name, err);
+        }
+      }
+    }
+  }
+
+  for plug in registered {
+    if let Err(err) = plug.on_start(Arc::downgrade(&ctx)).await {
+      log::error!("failed to start plugin {}: {:?}", plug.name(), err);
+    }
   }
 }
@@ -0,0 +1,120 @@
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+
+use tokio_util::sync::CancellationToken;
+
+use super::context::Context;
+use super::dispatcher;
+
+/// Coordinates stopping the bot: once triggered, no new updates are handled
+/// and background jobs wind down.
+#[derive(Debug)]
+pub struct Shutdown {
+  token: CancellationToken,
+
+  /// How long running commands get to finish before they are aborted.
+  pub deadline: Duration,
+}
+
+impl Shutdown {
+  pub fn new(deadline: Duration) -> Self {
+    Self {
+      token: CancellationToken::new(),
+      deadline,
+    }
+  }
+
+  pub fn new_shared(deadline: Duration) -> Arc<Self> {
+    Arc::new(Self::new(deadline))
+  }
+
+  pub fn trigger(
+    &self,
+    reason: &str,
+  ) {
+    if !self.token.is_cancelled() {
+      log::info!("shutting down: {}", reason);
+      self.token.cancel();
+    }
+  }
+
+  pub fn is_triggered(&self) -> bool {
+    self.token.is_cancelled()
+  }
+
+  /// Resolves once shutdown has been triggered.
+  pub async fn triggered(&self) {
+    self.token.cancelled().await
+  }
+}
+
+/// Triggers shutdown on SIGINT, and on SIGTERM where there is one.
+pub async fn watch_signals(shutdown: Arc<Shutdown>) {
+  #[cfg(unix)]
+  let terminate = async {
+    match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+      Ok(mut signal) => {
+        signal.recv().await;
+      }
+      Err(err) => {
+        log::warn!("cannot listen for SIGTERM: {:?}", err);
+        std::future::pending::<()>().await;
+      }
+    }
+  };
+
+  #[cfg(not(unix))]
+  let terminate = std::future::pending::<()>();
+
+  tokio::select! {
+    result = tokio::signal::ctrl_c() => match result {
+      Ok(()) => shutdown.trigger("received SIGINT"),
+      Err(err) => log::warn!("cannot listen for SIGINT: {:?}", err),
+    },
+    _ = terminate => shutdown.trigger("received SIGTERM"),
+    _ = shutdown.triggered() => {}
+  }
+}
+
+/// Runs once updates have stopped: waits for running commands up to the
+/// deadline, stops every plugin and flushes the database.
+pub async fn finish(ctx: &Mutex<Context>) {
+  // Hooks get a snapshot, so none of them runs under the context mutex.
+  let ctx = ctx.lock().await.clone();
+  let dp = dispatcher::current(&ctx.dp).await;
+  let shutdown = &ctx.shutdown;
+
+  let in_flight = dp.tasks.in_flight().len();
+  if in_flight > 0 {
+    log::info!(
+      "waiting up to {:?} for {} running commands",
+      shutdown.deadline,
+      in_flight
+    );
+  }
+
+  if !dp.tasks.drain(shutdown.deadline).await {
+    log::warn!("some commands did not finish before the shutdown deadline");
+  }
+
+  // Stop in reverse order, so plugins loaded later, which may build on
+  // earlier ones, go first.
+  for (name, plugin) in dp.plugins.iter().rev() {
+    log::debug!("stopping plugin {}", name);
+    if let Err(err) = plugin.on_stop(&ctx).await {
+      log::error!("failed to stop plugin {}: {:?}", name, err);
+    }
+  }
+
+  match ctx.db.get() {
+    Ok(conn) => {
+      if let Err(err) = conn.execute_batch("PRAGMA wal_checkpoint(TRUNCATE); PRAGMA optimize;") {
+        log::error!("failed to flush database: {:?}", err);
+      }
+    }
+    Err(err) => log::error!("failed to flush database: {:?}", err),
+  }
+
+  log::info!("shutdown complete");
+}
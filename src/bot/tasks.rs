@@ -0,0 +1,133 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use indexmap::IndexMap;
+use tokio::sync::Mutex;
+use tokio::task::JoinSet;
+
+use teloxide::types::{ChatId, UserId};
+
+#[derive(Clone, Debug)]
+pub struct InFlightCommand {
+  pub id: u64,
+  pub name: String,
+  pub chat_id: ChatId,
+  pub user_id: UserId,
+  pub started: Instant,
+}
+
+/// Owns the tasks running command handlers, so they can be listed while
+/// running and drained on shutdown.
+#[derive(Debug, Default)]
+pub struct TaskTracker {
+  next_id: AtomicU64,
+  tasks: Mutex<JoinSet<()>>,
+  in_flight: std::sync::Mutex<IndexMap<u64, InFlightCommand>>,
+}
+
+/// Removes a command from the in-flight list when its task ends, including
+/// when it panics or is aborted.
+struct InFlightGuard {
+  id: u64,
+  tracker: Arc<TaskTracker>,
+}
+
+impl Drop for InFlightGuard {
+  fn drop(&mut self) {
+    if let Ok(mut in_flight) = self.tracker.in_flight.lock() {
+      in_flight.shift_remove(&self.id);
+    }
+  }
+}
+
+impl TaskTracker {
+  pub fn new_shared() -> Arc<Self> {
+    Arc::new(Self::default())
+  }
+
+  fn reap(tasks: &mut JoinSet<()>) {
+    while let Some(result) = tasks.try_join_next() {
+      if let Err(err) = result {
+        log::error!("command task failed to complete: {:?}", err);
+      }
+    }
+  }
+
+  pub async fn spawn<F>(
+    self: &Arc<Self>,
+    name: String,
+    chat_id: ChatId,
+    user_id: UserId,
+    fut: F,
+  ) -> u64
+  where
+    F: Future<Output = ()> + Send + 'static,
+  {
+    let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+
+    if let Ok(mut in_flight) = self.in_flight.lock() {
+      in_flight.insert(
+        id,
+        InFlightCommand {
+          id,
+          name,
+          chat_id,
+          user_id,
+          started: Instant::now(),
+        },
+      );
+    }
+
+    let guard = InFlightGuard {
+      id,
+      tracker: self.clone(),
+    };
+
+    let mut tasks = self.tasks.lock().await;
+    Self::reap(&mut tasks);
+    tasks.spawn(async move {
+      let _guard = guard;
+      fut.await;
+    });
+
+    log::trace!("spawned command task {}, {} running", id, tasks.len());
+
+    id
+  }
+
+  pub fn in_flight(&self) -> Vec<InFlightCommand> {
+    match self.in_flight.lock() {
+      Ok(in_flight) => in_flight.values().cloned().collect(),
+      Err(_) => Vec::new(),
+    }
+  }
+
+  /// Waits for running commands to finish, aborting whatever is still running
+  /// once the deadline passes. Returns `true` if everything finished in time.
+  pub async fn drain(&self, deadline: Duration) -> bool {
+    let mut tasks = self.tasks.lock().await;
+
+    let finished = tokio::time::timeout(deadline, async {
+      while let Some(result) = tasks.join_next().await {
+        if let Err(err) = result {
+          log::error!("command task failed to complete: {:?}", err);
+        }
+      }
+    })
+    .await
+    .is_ok();
+
+    if !finished {
+      log::warn!(
+        "aborting {} command tasks still running after {:?}",
+        tasks.len(),
+        deadline
+      );
+      tasks.abort_all();
+      while tasks.join_next().await.is_some() {}
+    }
+
+    finished
+  }
+}
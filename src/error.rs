@@ -1,15 +1,48 @@
 use teloxide::prelude::Requester;
 
+use crate::permissions::roles::RoleError;
+use crate::plugins::core::CoreError;
 use crate::utils::style::{DefaultStyle, Style};
 
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
   #[error("context is disposed")]
   ContextDisposed,
+
+  #[error("plugin {0} is already registered")]
+  PluginAlreadyRegistered(String),
+
+  #[error("invalid command name '{0}'")]
+  InvalidCommandName(String),
+
+  #[error("'{name}' from plugin {plugin} collides with command {existing} from plugin {owner}")]
+  CommandCollision {
+    name: String,
+    plugin: String,
+    existing: String,
+    owner: String,
+  },
   // #[error(transparent)]
   // Teloxide(#[from] teloxide::RequestError),
 }
 
+/// Returns the message of errors that are meant to be shown to users.
+fn user_message(err: &anyhow::Error) -> Option<String> {
+  if let Some(err) = err.downcast_ref::<Error>() {
+    return Some(err.to_string());
+  }
+
+  if let Some(err) = err.downcast_ref::<CoreError>() {
+    return Some(err.to_string());
+  }
+
+  err.downcast_ref::<RoleError>().map(|err| err.to_string())
+}
+
+/// Reports an error to the chat the message came from. Only bot and plugin
+/// errors are shown verbatim; anything else (database, network, ...) gets a
+/// generic reply, so internals never leak into chats. Callers log the full
+/// error themselves.
 pub async fn emit(
   bot: Option<teloxide::Bot>,
   msg: Option<teloxide::prelude::Message>,
@@ -18,8 +51,11 @@ pub async fn emit(
   let _err = err.into();
 
   if let (Some(bot), Some(msg)) = (bot, msg) {
+    let text = user_message(&_err)
+      .unwrap_or_else(|| "something went wrong while handling this message".to_string());
+
     let _ = bot
-      .send_message(msg.chat.id, format!("{} {:?}", DefaultStyle::s_err(), _err))
+      .send_message(msg.chat.id, format!("{} {}", DefaultStyle::s_err(), text))
       .await;
   }
 
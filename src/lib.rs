@@ -0,0 +1,17 @@
+pub mod error;
+
+pub mod audit;
+pub mod bot;
+pub mod permissions;
+pub mod settings;
+pub mod utils;
+
+pub mod plugins;
+
+use once_cell::sync::Lazy;
+use std::time::Instant;
+
+pub static START_TIME: Lazy<Instant> = Lazy::new(|| {
+  log::debug!("initializing start time");
+  Instant::now()
+});
@@ -1,14 +1,16 @@
-pub mod error;
-
-pub mod bot;
-pub mod permissions;
-pub mod utils;
-
-pub mod plugins;
-
-use crate::permissions::{manager::PermissionManager, types::Permission};
+use tebot::audit::manager::AuditLog;
+use tebot::permissions::{
+  admins::AdminResolver,
+  blocklist::BlockList,
+  manager::PermissionManager,
+  roles::RoleManager,
+  sweeper,
+  types::{Permission, Scope},
+};
+use tebot::settings::manager::SettingsManager;
+use tebot::{plugins, utils, START_TIME};
 
-use bot::{config::Config, context::Context, dispatcher, plugin};
+use tebot::bot::{config::Config, context::Context, dispatcher, plugin, shutdown};
 
 use teloxide::{
   dptree,
@@ -23,13 +25,6 @@ use r2d2::Pool;
 use r2d2_sqlite::SqliteConnectionManager;
 
 use dotenvy::dotenv;
-use once_cell::sync::Lazy;
-use std::time::Instant;
-
-pub static START_TIME: Lazy<Instant> = Lazy::new(|| {
-  log::debug!("initializing start time");
-  Instant::now()
-});
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
@@ -43,51 +38,84 @@ async fn main() -> anyhow::Result<()> {
   let cfg = Config::new_shared(
     utils::env::get_token().await,
     utils::env::get_prefixes().await,
+    utils::env::get_case_insensitive().await,
   );
   let _conn_mgr = SqliteConnectionManager::file(utils::env::get_db_path().await);
 
   let pool = Arc::new(Pool::new(_conn_mgr)?);
 
-  let perm_mgr = PermissionManager::new_shared(pool.clone())?;
-  let bot = Arc::new(Bot::new(cfg.lock().await.get_token()));
+  let perm_mgr =
+    PermissionManager::new_shared(pool.clone(), utils::env::get_default_permission().await)?;
+  let roles = RoleManager::new_shared(pool.clone())?;
+  let blocklist = BlockList::new_shared(pool.clone())?;
+  let audit = AuditLog::new_shared(pool.clone())?;
+  let settings = SettingsManager::new_shared(pool.clone())?;
+  let admins = AdminResolver::new_shared(utils::env::get_admin_cache_ttl().await);
+  let bot = Arc::new(Bot::new(cfg.read().await.get_token()));
   let dp = dispatcher::Dispatcher::new_shared(Weak::new());
   let style = Arc::new(utils::style::DefaultStyle);
+  let shutdown = shutdown::Shutdown::new_shared(utils::env::get_shutdown_timeout().await);
   let ctx = Arc::new(Mutex::new(Context::new(
     cfg.clone(),
     pool.clone(),
     perm_mgr.clone(),
+    admins.clone(),
+    roles.clone(),
+    blocklist.clone(),
+    audit.clone(),
+    settings.clone(),
     bot.clone(),
     dp.clone(),
+    shutdown.clone(),
     style.clone(),
   )));
 
   {
     if let Ok(owner_id) = utils::env::get_owner_id().await {
-      perm_mgr.lock().await.set(owner_id, Permission::OWNER)?;
+      perm_mgr.set(Scope::Global, owner_id, Permission::OWNER)?;
     }
   }
 
   {
-    dp.lock().await.context = Arc::downgrade(&ctx);
+    Arc::make_mut(&mut *dp.write().await).context = Arc::downgrade(&ctx);
   }
 
   {
     plugin::register_all(dp.clone(), plugins::all().await).await;
   }
 
+  tokio::spawn(sweeper::run(
+    Arc::downgrade(&ctx),
+    utils::env::get_grant_sweep_interval().await,
+  ));
+
   let me = bot.get_me().await?;
   log::info!("bot logged in as {} [id: {}]", me.full_name(), me.id);
 
+  {
+    cfg.write().await.set_username(me.username.clone());
+  }
+
+  tokio::spawn(shutdown::watch_signals(shutdown.clone()));
+
   let handler = dptree::entry().endpoint({
     let dp = dp.clone();
+    let shutdown = shutdown.clone();
 
     move |update: teloxide::prelude::Update, bot: Arc<Bot>| {
       let dp = dp.clone();
+      let shutdown = shutdown.clone();
 
       async move {
+        if shutdown.is_triggered() {
+          log::trace!("dropping update {}: shutting down", update.id.0);
+          return Ok(());
+        }
+
         log::trace!("new update received, kind: {:?}", update.kind);
 
-        if let Err(err) = dp.lock().await.handle_update((*bot).clone(), update).await {
+        let dp = dispatcher::current(&dp).await;
+        if let Err(err) = dp.handle_update((*bot).clone(), update).await {
           log::error!("error handling update: {:?}", err);
         }
 
@@ -96,11 +124,29 @@ async fn main() -> anyhow::Result<()> {
     }
   });
 
-  log::trace!("starting dispatcher");
-  Dispatcher::builder(bot.clone(), handler)
-    .build()
-    .dispatch()
-    .await;
+  let mut tg_dispatcher = Dispatcher::builder(bot.clone(), handler).build();
+
+  tokio::spawn({
+    let shutdown = shutdown.clone();
+    let token = tg_dispatcher.shutdown_token();
+
+    async move {
+      shutdown.triggered().await;
+      match token.shutdown() {
+        Ok(stopped) => stopped.await,
+        Err(err) => log::warn!("cannot stop polling: {}", err),
+      }
+    }
+  });
+
+  if !shutdown.is_triggered() {
+    log::trace!("starting dispatcher");
+    tg_dispatcher.dispatch().await;
+  }
+
+  // Polling also stops on its own, e.g. when the token is rejected.
+  shutdown.trigger("dispatcher stopped");
+  shutdown::finish(&ctx).await;
 
   Ok(())
 }
@@ -0,0 +1,111 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use teloxide::prelude::{ChatId, Requester, UserId};
+use teloxide::Bot;
+
+use super::types::Permission;
+
+#[derive(Debug, Clone)]
+struct CachedAdmins {
+  fetched: Instant,
+  members: HashMap<UserId, Permission>,
+}
+
+/// Maps Telegram chat creators and administrators onto `OWNER` and `ADMIN`
+/// inside their chat, caching each chat's administrator list for `ttl`.
+#[derive(Debug)]
+pub struct AdminResolver {
+  pub ttl: Duration,
+  cache: std::sync::Mutex<HashMap<ChatId, CachedAdmins>>,
+}
+
+impl AdminResolver {
+  pub fn new(ttl: Duration) -> Self {
+    Self {
+      ttl,
+      cache: std::sync::Mutex::new(HashMap::new()),
+    }
+  }
+
+  pub fn new_shared(ttl: Duration) -> Arc<Self> {
+    Arc::new(Self::new(ttl))
+  }
+
+  fn cached(&self, chat_id: ChatId) -> Option<HashMap<UserId, Permission>> {
+    let cache = self.cache.lock().unwrap();
+    cache
+      .get(&chat_id)
+      .filter(|entry| entry.fetched.elapsed() < self.ttl)
+      .map(|entry| entry.members.clone())
+  }
+
+  async fn fetch(
+    &self,
+    bot: &Bot,
+    chat_id: ChatId,
+  ) -> anyhow::Result<HashMap<UserId, Permission>> {
+    let members: HashMap<UserId, Permission> = bot
+      .get_chat_administrators(chat_id)
+      .await?
+      .into_iter()
+      .filter_map(|member| {
+        if member.is_owner() {
+          Some((member.user.id, Permission::OWNER))
+        } else if member.is_administrator() {
+          Some((member.user.id, Permission::ADMIN))
+        } else {
+          None
+        }
+      })
+      .collect();
+
+    log::trace!("fetched {} administrators of chat {}", members.len(), chat_id);
+
+    self.cache.lock().unwrap().insert(
+      chat_id,
+      CachedAdmins {
+        fetched: Instant::now(),
+        members: members.clone(),
+      },
+    );
+
+    Ok(members)
+  }
+
+  /// Returns the permission the user's Telegram role grants in the chat, if
+  /// any. Private chats have no administrators.
+  pub async fn lookup(
+    &self,
+    bot: &Bot,
+    chat_id: ChatId,
+    user_id: UserId,
+  ) -> anyhow::Result<Option<Permission>> {
+    if !chat_id.is_group() && !chat_id.is_channel_or_supergroup() {
+      return Ok(None);
+    }
+
+    let members = match self.cached(chat_id) {
+      Some(members) => members,
+      None => self.fetch(bot, chat_id).await?,
+    };
+
+    let perm = members.get(&user_id).copied();
+
+    log::trace!(
+      "telegram role of user {} in chat {} maps to {:?}",
+      user_id,
+      chat_id,
+      perm
+    );
+
+    Ok(perm)
+  }
+
+  pub fn invalidate(&self, chat_id: ChatId) {
+    if self.cache.lock().unwrap().remove(&chat_id).is_some() {
+      log::trace!("invalidated administrator cache of chat {}", chat_id);
+    }
+  }
+}
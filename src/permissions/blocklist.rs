@@ -0,0 +1,222 @@
+use chrono::{DateTime, Utc};
+use rusqlite::params;
+use std::sync::Arc;
+use std::time::Duration;
+
+use r2d2::Pool;
+use r2d2_sqlite::SqliteConnectionManager;
+
+use teloxide::prelude::{ChatId, UserId};
+
+/// Something the bot can be told to ignore.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+pub enum BlockTarget {
+  User(UserId),
+  Chat(ChatId),
+}
+
+impl BlockTarget {
+  fn kind(&self) -> &'static str {
+    match self {
+      BlockTarget::User(_) => "user",
+      BlockTarget::Chat(_) => "chat",
+    }
+  }
+
+  fn id(&self) -> i64 {
+    match self {
+      BlockTarget::User(user_id) => user_id.0 as i64,
+      BlockTarget::Chat(chat_id) => chat_id.0,
+    }
+  }
+
+  fn from_row(
+    kind: &str,
+    id: i64,
+  ) -> Option<Self> {
+    match kind {
+      "user" => Some(BlockTarget::User(UserId(id as u64))),
+      "chat" => Some(BlockTarget::Chat(ChatId(id))),
+      _ => None,
+    }
+  }
+}
+
+impl std::fmt::Display for BlockTarget {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      BlockTarget::User(user_id) => write!(f, "user {}", user_id),
+      BlockTarget::Chat(chat_id) => write!(f, "chat {}", chat_id),
+    }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockEntry {
+  pub target: BlockTarget,
+  pub reason: Option<String>,
+  pub created_at: DateTime<Utc>,
+
+  /// When the entry stops applying; `None` blocks forever.
+  pub expires_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct BlockList {
+  pub db: Arc<Pool<SqliteConnectionManager>>,
+}
+
+impl BlockList {
+  pub fn new(db: Arc<Pool<SqliteConnectionManager>>) -> anyhow::Result<Self> {
+    let mgr = Self { db };
+    mgr.init_schema()?;
+    Ok(mgr)
+  }
+
+  pub fn new_shared(db: Arc<Pool<SqliteConnectionManager>>) -> anyhow::Result<Arc<Self>> {
+    let mgr = Self::new(db)?;
+    Ok(Arc::new(mgr))
+  }
+
+  fn init_schema(&self) -> anyhow::Result<()> {
+    let conn = self.db.get()?;
+    conn.execute(
+      "CREATE TABLE IF NOT EXISTS blocklist (
+                kind       TEXT NOT NULL,
+                id         INTEGER NOT NULL,
+                reason     TEXT,
+                created_at INTEGER NOT NULL,
+                expires_at INTEGER,
+                PRIMARY KEY (kind, id)
+            )",
+      [],
+    )?;
+    Ok(())
+  }
+
+  /// Blocks a target, replacing any previous entry for it.
+  pub fn block(
+    &self,
+    target: BlockTarget,
+    reason: Option<&str>,
+    duration: Option<Duration>,
+  ) -> anyhow::Result<BlockEntry> {
+    let now = Utc::now();
+    let expires_at = match duration {
+      Some(duration) => Some(now + chrono::Duration::from_std(duration)?),
+      None => None,
+    };
+
+    let conn = self.db.get()?;
+    conn.execute(
+      "INSERT INTO blocklist (kind, id, reason, created_at, expires_at)
+             VALUES (?1, ?2, ?3, ?4, ?5)
+             ON CONFLICT(kind, id) DO UPDATE SET
+               reason = excluded.reason,
+               created_at = excluded.created_at,
+               expires_at = excluded.expires_at",
+      params![
+        target.kind(),
+        target.id(),
+        reason,
+        now.timestamp(),
+        expires_at.map(|at| at.timestamp())
+      ],
+    )?;
+
+    log::trace!("blocked {} until {:?}: {:?}", target, expires_at, reason);
+
+    Ok(BlockEntry {
+      target,
+      reason: reason.map(|r| r.to_string()),
+      created_at: now,
+      expires_at,
+    })
+  }
+
+  /// Removes a target's entry, returning whether there was one.
+  pub fn unblock(&self, target: BlockTarget) -> anyhow::Result<bool> {
+    let conn = self.db.get()?;
+    let removed = conn.execute(
+      "DELETE FROM blocklist WHERE kind = ?1 AND id = ?2",
+      params![target.kind(), target.id()],
+    )?;
+
+    log::trace!("unblocked {}: {}", target, removed > 0);
+
+    Ok(removed > 0)
+  }
+
+  /// Drops entries whose expiry has passed.
+  pub fn purge_expired(&self) -> anyhow::Result<usize> {
+    let conn = self.db.get()?;
+    let purged = conn.execute(
+      "DELETE FROM blocklist WHERE expires_at IS NOT NULL AND expires_at <= ?1",
+      params![Utc::now().timestamp()],
+    )?;
+
+    if purged > 0 {
+      log::debug!("purged {} expired blocklist entries", purged);
+    }
+
+    Ok(purged)
+  }
+
+  pub fn is_blocked(&self, target: BlockTarget) -> anyhow::Result<bool> {
+    let conn = self.db.get()?;
+    let blocked = conn.query_row(
+      "SELECT EXISTS (
+               SELECT 1 FROM blocklist
+               WHERE kind = ?1 AND id = ?2
+                 AND (expires_at IS NULL OR expires_at > ?3)
+             )",
+      params![target.kind(), target.id(), Utc::now().timestamp()],
+      |row| row.get::<_, bool>(0),
+    )?;
+
+    Ok(blocked)
+  }
+
+  /// Active entries, soonest expiry last and permanent ones first.
+  pub fn entries(&self) -> anyhow::Result<Vec<BlockEntry>> {
+    self.purge_expired()?;
+
+    let conn = self.db.get()?;
+    let mut stmt = conn.prepare(
+      "SELECT kind, id, reason, created_at, expires_at FROM blocklist
+             ORDER BY expires_at IS NOT NULL, expires_at, created_at",
+    )?;
+
+    let rows = stmt.query_map([], |row| {
+      Ok((
+        row.get::<_, String>(0)?,
+        row.get::<_, i64>(1)?,
+        row.get::<_, Option<String>>(2)?,
+        row.get::<_, i64>(3)?,
+        row.get::<_, Option<i64>>(4)?,
+      ))
+    })?;
+
+    let mut entries = Vec::new();
+    for row in rows {
+      let (kind, id, reason, created_at, expires_at) = row?;
+
+      let target = match BlockTarget::from_row(&kind, id) {
+        Some(target) => target,
+        None => {
+          log::error!("unknown blocklist entry kind '{}'", kind);
+          continue;
+        }
+      };
+
+      entries.push(BlockEntry {
+        target,
+        reason,
+        created_at: DateTime::from_timestamp(created_at, 0).unwrap_or_default(),
+        expires_at: expires_at.and_then(|at| DateTime::from_timestamp(at, 0)),
+      });
+    }
+
+    Ok(entries)
+  }
+}
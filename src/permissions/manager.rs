@@ -1,36 +1,80 @@
-use rusqlite::params;
+use chrono::{DateTime, Utc};
+use rusqlite::{params, Connection, OptionalExtension, TransactionBehavior};
 use std::sync::Arc;
-use tokio::sync::Mutex;
+use std::time::Duration;
 
 use r2d2::Pool;
 use r2d2_sqlite::SqliteConnectionManager;
 
-use teloxide::prelude::UserId;
+use teloxide::prelude::{ChatId, UserId};
 
-use super::types::{Permission, PermissionMap};
+use super::types::{
+  CommandOverride, ImportMode, Permission, PermissionMap, PermissionSnapshot, Scope,
+  SnapshotChange, TemporaryGrant,
+};
 
 #[derive(Debug, Clone)]
 pub struct PermissionManager {
   pub db: Arc<Pool<SqliteConnectionManager>>,
+
+  /// Permission of users without an entry in either scope, unless their chat
+  /// sets its own default.
+  pub default: Permission,
 }
 
 impl PermissionManager {
-  pub fn new(db: Arc<Pool<SqliteConnectionManager>>) -> anyhow::Result<Self> {
-    let mgr = Self { db };
+  pub fn new(
+    db: Arc<Pool<SqliteConnectionManager>>,
+    default: Permission,
+  ) -> anyhow::Result<Self> {
+    let mgr = Self { db, default };
     mgr.init_schema()?;
     Ok(mgr)
   }
 
-  pub fn new_shared(db: Arc<Pool<SqliteConnectionManager>>) -> anyhow::Result<Arc<Mutex<Self>>> {
-    let mgr = Self::new(db)?;
-    Ok(Arc::new(Mutex::new(mgr)))
+  pub fn new_shared(
+    db: Arc<Pool<SqliteConnectionManager>>,
+    default: Permission,
+  ) -> anyhow::Result<Arc<Self>> {
+    let mgr = Self::new(db, default)?;
+    Ok(Arc::new(mgr))
   }
 
   fn init_schema(&self) -> anyhow::Result<()> {
     let conn = self.db.get()?;
+    Self::migrate_unscoped(&conn)?;
     conn.execute(
       "CREATE TABLE IF NOT EXISTS permissions (
-                user_id TEXT PRIMARY KEY,
+                chat_id INTEGER NOT NULL DEFAULT 0,
+                user_id TEXT NOT NULL,
+                flags   INTEGER NOT NULL,
+                PRIMARY KEY (chat_id, user_id)
+            )",
+      [],
+    )?;
+    conn.execute(
+      "CREATE TABLE IF NOT EXISTS temporary_grants (
+                id         INTEGER PRIMARY KEY AUTOINCREMENT,
+                chat_id    INTEGER NOT NULL DEFAULT 0,
+                user_id    TEXT NOT NULL,
+                flags      INTEGER NOT NULL,
+                granted_by TEXT,
+                expires_at INTEGER NOT NULL
+            )",
+      [],
+    )?;
+    conn.execute(
+      "CREATE TABLE IF NOT EXISTS command_overrides (
+                chat_id INTEGER NOT NULL DEFAULT 0,
+                command TEXT NOT NULL,
+                flags   INTEGER,
+                PRIMARY KEY (chat_id, command)
+            )",
+      [],
+    )?;
+    conn.execute(
+      "CREATE TABLE IF NOT EXISTS permission_defaults (
+                chat_id INTEGER PRIMARY KEY,
                 flags   INTEGER NOT NULL
             )",
       [],
@@ -38,109 +82,504 @@ impl PermissionManager {
     Ok(())
   }
 
-  pub fn reset(&self, user_id: UserId) -> anyhow::Result<()> {
+  /// Moves entries of the old `permissions` table, keyed by user only, into
+  /// the global scope of the scoped table.
+  fn migrate_unscoped(conn: &Connection) -> anyhow::Result<()> {
+    let columns: Vec<String> = conn
+      .prepare("PRAGMA table_info(permissions)")?
+      .query_map([], |row| row.get(1))?
+      .collect::<Result<_, _>>()?;
+
+    if columns.is_empty() || columns.iter().any(|c| c == "chat_id") {
+      return Ok(());
+    }
+
+    log::info!("migrating permissions table to per-chat scopes");
+
+    conn.execute_batch(
+      "BEGIN;
+       ALTER TABLE permissions RENAME TO permissions_unscoped;
+       CREATE TABLE permissions (
+                chat_id INTEGER NOT NULL DEFAULT 0,
+                user_id TEXT NOT NULL,
+                flags   INTEGER NOT NULL,
+                PRIMARY KEY (chat_id, user_id)
+            );
+       INSERT INTO permissions (chat_id, user_id, flags)
+            SELECT 0, user_id, flags FROM permissions_unscoped;
+       DROP TABLE permissions_unscoped;
+       COMMIT;",
+    )?;
+
+    Ok(())
+  }
+
+  /// Runs `f` in a single transaction, committing only if it succeeds.
+  ///
+  /// The transaction takes the write lock up front, so reads made inside it
+  /// cannot be invalidated by another writer before its own writes land.
+  fn transaction<T>(
+    &self,
+    f: impl FnOnce(&Connection) -> anyhow::Result<T>,
+  ) -> anyhow::Result<T> {
+    let mut conn = self.db.get()?;
+    let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+    let result = f(&tx)?;
+    tx.commit()?;
+    Ok(result)
+  }
+
+  pub fn reset(&self, scope: Scope, user_id: UserId) -> anyhow::Result<()> {
+    self.transaction(|conn| {
+      conn.execute(
+        "DELETE FROM permissions WHERE chat_id = ?1 AND user_id = ?2",
+        params![scope.key(), user_id.0],
+      )?;
+      conn.execute(
+        "DELETE FROM temporary_grants WHERE chat_id = ?1 AND user_id = ?2",
+        params![scope.key(), user_id.0],
+      )?;
+      Ok(())
+    })?;
+
+    log::trace!("removed all permissions for user {} in {}", user_id, scope);
+    Ok(())
+  }
+
+  pub fn clear(&self, scope: Scope) -> anyhow::Result<()> {
     let conn = self.db.get()?;
     conn.execute(
-      "DELETE FROM permissions WHERE user_id = ?1",
-      params![user_id.0],
+      "DELETE FROM permissions WHERE chat_id = ?1",
+      params![scope.key()],
     )?;
+    Ok(())
+  }
+
+  /// Returns the user's explicit permission in exactly this scope, if any.
+  pub fn lookup(&self, scope: Scope, user_id: UserId) -> anyhow::Result<Option<Permission>> {
+    let conn = self.db.get()?;
+    let perm = Self::lookup_in(&conn, scope, user_id)?;
+
+    log::trace!("lookup permission for user {} in {}: {:?}", user_id, scope, perm);
+
+    Ok(perm)
+  }
+
+  fn lookup_in(
+    conn: &Connection,
+    scope: Scope,
+    user_id: UserId,
+  ) -> anyhow::Result<Option<Permission>> {
+    let perm = conn
+      .query_row(
+        "SELECT flags FROM permissions WHERE chat_id = ?1 AND user_id = ?2",
+        params![scope.key(), user_id.0],
+        |row| Ok(Permission::from_bits_truncate(row.get::<_, u32>(0)?)),
+      )
+      .optional()?;
+
+    Ok(perm)
+  }
+
+  /// Finds the entry that decides the user's permission in a scope: the
+  /// chat's own entry, then the global one. Returns the scope it came from.
+  pub fn resolve(
+    &self,
+    scope: Scope,
+    user_id: UserId,
+  ) -> anyhow::Result<Option<(Scope, Permission)>> {
+    let conn = self.db.get()?;
+    Self::resolve_in(&conn, scope, user_id)
+  }
+
+  fn resolve_in(
+    conn: &Connection,
+    scope: Scope,
+    user_id: UserId,
+  ) -> anyhow::Result<Option<(Scope, Permission)>> {
+    if let Scope::Chat(_) = scope
+      && let Some(perm) = Self::lookup_in(conn, scope, user_id)?
+    {
+      return Ok(Some((scope, perm)));
+    }
+
+    Ok(Self::lookup_in(conn, Scope::Global, user_id)?.map(|perm| (Scope::Global, perm)))
+  }
+
+  /// Returns the user's permission in a scope, falling back to the global
+  /// entry, then to the chat's default and finally to the global default.
+  ///
+  /// Unexpired temporary grants in the scope are added on top. Global ones
+  /// are only added when the chat has no entry of its own for the user, so
+  /// they cannot lift a chat restriction.
+  pub fn get(&self, scope: Scope, user_id: UserId) -> anyhow::Result<Permission> {
+    let resolved = self.resolve(scope, user_id)?;
+    let chat_entry = matches!(resolved, Some((Scope::Chat(_), _)));
+
+    let mut perm = match resolved {
+      Some((_, perm)) => perm,
+      None => match scope {
+        Scope::Global => self.default,
+        Scope::Chat(chat_id) => self.default_for(chat_id)?,
+      },
+    };
+
+    for grant in self.temporary_grants(scope, user_id)? {
+      if grant.scope == Scope::Global && chat_entry {
+        continue;
+      }
+      perm |= grant.perm;
+    }
+
+    log::trace!("get permission for user {} in {}: {:?}", user_id, scope, perm);
+
+    Ok(perm)
+  }
+
+  /// Returns the chat's own default permission, if it has one.
+  pub fn chat_default(&self, chat_id: ChatId) -> anyhow::Result<Option<Permission>> {
+    let conn = self.db.get()?;
+    let perm = conn
+      .query_row(
+        "SELECT flags FROM permission_defaults WHERE chat_id = ?1",
+        params![chat_id.0],
+        |row| Ok(Permission::from_bits_truncate(row.get::<_, u32>(0)?)),
+      )
+      .optional()?;
+
+    Ok(perm)
+  }
+
+  pub fn default_for(&self, chat_id: ChatId) -> anyhow::Result<Permission> {
+    Ok(self.chat_default(chat_id)?.unwrap_or(self.default))
+  }
+
+  pub fn set_default(&self, chat_id: ChatId, perm: Permission) -> anyhow::Result<()> {
+    let conn = self.db.get()?;
+    conn.execute(
+      "INSERT INTO permission_defaults (chat_id, flags)
+             VALUES (?1, ?2)
+             ON CONFLICT(chat_id) DO UPDATE SET flags = excluded.flags",
+      params![chat_id.0, perm.bits()],
+    )?;
+
+    log::trace!("set default permission for chat {}: {:?}", chat_id, perm);
 
-    log::trace!("removed all permissions for user {}", user_id);
     Ok(())
   }
 
-  pub fn clear(&self) -> anyhow::Result<()> {
+  pub fn reset_default(&self, chat_id: ChatId) -> anyhow::Result<()> {
     let conn = self.db.get()?;
-    conn.execute("DELETE FROM permissions", [])?;
+    conn.execute(
+      "DELETE FROM permission_defaults WHERE chat_id = ?1",
+      params![chat_id.0],
+    )?;
+
+    log::trace!("reset default permission for chat {}", chat_id);
+
     Ok(())
   }
 
-  pub fn get(&self, user_id: UserId) -> anyhow::Result<Permission> {
+  /// Returns the override of a command in exactly this scope, if any.
+  pub fn lookup_override(
+    &self,
+    scope: Scope,
+    command: &str,
+  ) -> anyhow::Result<Option<CommandOverride>> {
+    let conn = self.db.get()?;
+    let ovr = conn
+      .query_row(
+        "SELECT flags FROM command_overrides WHERE chat_id = ?1 AND command = ?2",
+        params![scope.key(), command],
+        |row| Ok(CommandOverride::from_bits(row.get::<_, Option<u32>>(0)?)),
+      )
+      .optional()?;
+
+    Ok(ovr)
+  }
+
+  /// Returns the override in effect for a command: the chat's own, then the
+  /// global one.
+  pub fn command_override(
+    &self,
+    scope: Scope,
+    command: &str,
+  ) -> anyhow::Result<Option<CommandOverride>> {
+    let mut ovr = self.lookup_override(scope, command)?;
+    if ovr.is_none() && scope != Scope::Global {
+      ovr = self.lookup_override(Scope::Global, command)?;
+    }
+
+    log::trace!("override of command {} in {}: {:?}", command, scope, ovr);
+
+    Ok(ovr)
+  }
+
+  pub fn set_override(
+    &self,
+    scope: Scope,
+    command: &str,
+    ovr: CommandOverride,
+  ) -> anyhow::Result<()> {
     let conn = self.db.get()?;
-    let perm = conn.query_row(
-      "SELECT flags FROM permissions WHERE user_id = ?1",
-      params![user_id.0],
-      |row| Ok(Permission::from_bits_truncate(row.get::<_, u32>(0)?)),
+    conn.execute(
+      "INSERT INTO command_overrides (chat_id, command, flags)
+             VALUES (?1, ?2, ?3)
+             ON CONFLICT(chat_id, command) DO UPDATE SET flags = excluded.flags",
+      params![scope.key(), command, ovr.bits()],
     )?;
 
-    log::trace!("get permission for user {}: {:?}", user_id, perm);
+    log::trace!("set override of command {} in {}: {:?}", command, scope, ovr);
 
-    Ok(perm)
+    Ok(())
   }
 
-  pub fn set(&self, user_id: UserId, perm: Permission) -> anyhow::Result<()> {
+  pub fn reset_override(
+    &self,
+    scope: Scope,
+    command: &str,
+  ) -> anyhow::Result<()> {
     let conn = self.db.get()?;
     conn.execute(
-      "INSERT INTO permissions (user_id, flags)
-             VALUES (?1, ?2)
-             ON CONFLICT(user_id) DO UPDATE SET flags = excluded.flags",
-      params![user_id.0, perm.bits()],
+      "DELETE FROM command_overrides WHERE chat_id = ?1 AND command = ?2",
+      params![scope.key(), command],
     )?;
 
-    log::trace!("set permission for user {}: {:?}", user_id, perm);
+    log::trace!("reset override of command {} in {}", command, scope);
 
     Ok(())
   }
 
-  pub fn grant(&self, user_id: UserId, perm: Permission) -> anyhow::Result<()> {
-    let current = self.get(user_id).unwrap_or(Permission::NONE);
-    self.set(user_id, current | perm)?;
+  pub fn overrides(&self, scope: Scope) -> anyhow::Result<Vec<(String, CommandOverride)>> {
+    let conn = self.db.get()?;
+    let mut stmt = conn.prepare(
+      "SELECT command, flags FROM command_overrides WHERE chat_id = ?1 ORDER BY command",
+    )?;
+
+    let rows = stmt.query_map(params![scope.key()], |row| {
+      Ok((
+        row.get::<_, String>(0)?,
+        CommandOverride::from_bits(row.get::<_, Option<u32>>(1)?),
+      ))
+    })?;
+
+    Ok(rows.collect::<Result<_, _>>()?)
+  }
+
+  pub fn set(&self, scope: Scope, user_id: UserId, perm: Permission) -> anyhow::Result<()> {
+    let conn = self.db.get()?;
+    conn.execute(
+      "INSERT INTO permissions (chat_id, user_id, flags)
+             VALUES (?1, ?2, ?3)
+             ON CONFLICT(chat_id, user_id) DO UPDATE SET flags = excluded.flags",
+      params![scope.key(), user_id.0, perm.bits()],
+    )?;
+
+    log::trace!("set permission for user {} in {}: {:?}", user_id, scope, perm);
+
+    Ok(())
+  }
+
+  /// Adds to the entry currently deciding the user's permission, so granting
+  /// in a chat starts from the user's global permission.
+  pub fn grant(&self, scope: Scope, user_id: UserId, perm: Permission) -> anyhow::Result<()> {
+    let current = self.transaction(|conn| {
+      let current = Self::resolve_in(conn, scope, user_id)?;
+
+      conn.execute(
+        "INSERT INTO permissions (chat_id, user_id, flags)
+               VALUES (?1, ?2, ?3 | COALESCE(
+                 (SELECT flags FROM permissions WHERE chat_id = 0 AND user_id = ?2), 0))
+               ON CONFLICT(chat_id, user_id) DO UPDATE SET flags = flags | ?3",
+        params![scope.key(), user_id.0, perm.bits()],
+      )?;
+
+      Ok(current.map_or(Permission::NONE, |(_, perm)| perm))
+    })?;
 
     log::trace!(
-      "grant permission {:?} to user {}, previous {:?}",
+      "grant permission {:?} to user {} in {}, previous {:?}",
       perm,
       user_id,
+      scope,
       current
     );
 
     Ok(())
   }
 
-  pub fn revoke(&self, user_id: UserId, perm: Permission) -> anyhow::Result<()> {
-    let current = self.get(user_id)?;
-    self.set(user_id, current - perm)?;
+  /// Grants a permission that lapses after `duration`, leaving the user's
+  /// entry untouched. Returns when it expires.
+  pub fn grant_for(
+    &self,
+    scope: Scope,
+    user_id: UserId,
+    perm: Permission,
+    duration: Duration,
+    granted_by: Option<UserId>,
+  ) -> anyhow::Result<DateTime<Utc>> {
+    let expires_at = Utc::now() + chrono::Duration::from_std(duration)?;
+
+    let conn = self.db.get()?;
+    conn.execute(
+      "INSERT INTO temporary_grants (chat_id, user_id, flags, granted_by, expires_at)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+      params![
+        scope.key(),
+        user_id.0,
+        perm.bits(),
+        granted_by.map(|id| id.0),
+        expires_at.timestamp()
+      ],
+    )?;
 
     log::trace!(
-      "revoke permission {:?} from user {}, previous {:?}",
+      "grant permission {:?} to user {} in {} until {}",
       perm,
       user_id,
-      current
+      scope,
+      expires_at
     );
 
-    Ok(())
+    Ok(expires_at)
+  }
+
+  fn read_grants(
+    conn: &Connection,
+    sql: &str,
+    params: impl rusqlite::Params,
+  ) -> anyhow::Result<Vec<TemporaryGrant>> {
+    let mut stmt = conn.prepare(sql)?;
+
+    let rows = stmt.query_map(params, |row| {
+      let parse_id = |idx: usize, raw: String| {
+        raw.parse::<u64>().map(UserId).map_err(|e| {
+          rusqlite::Error::FromSqlConversionFailure(idx, rusqlite::types::Type::Text, Box::new(e))
+        })
+      };
+
+      Ok(TemporaryGrant {
+        id: row.get(0)?,
+        scope: Scope::from_key(row.get(1)?),
+        user_id: parse_id(2, row.get(2)?)?,
+        perm: Permission::from_bits_truncate(row.get(3)?),
+        granted_by: row
+          .get::<_, Option<String>>(4)?
+          .map(|raw| parse_id(4, raw))
+          .transpose()?,
+        expires_at: DateTime::from_timestamp(row.get(5)?, 0).unwrap_or_default(),
+      })
+    })?;
+
+    Ok(rows.collect::<Result<_, _>>()?)
+  }
+
+  /// Unexpired temporary grants of the user in the scope and the global scope.
+  pub fn temporary_grants(
+    &self,
+    scope: Scope,
+    user_id: UserId,
+  ) -> anyhow::Result<Vec<TemporaryGrant>> {
+    let conn = self.db.get()?;
+    Self::read_grants(
+      &conn,
+      "SELECT id, chat_id, user_id, flags, granted_by, expires_at FROM temporary_grants
+             WHERE user_id = ?1 AND chat_id IN (0, ?2) AND expires_at > ?3
+             ORDER BY expires_at",
+      params![user_id.0, scope.key(), Utc::now().timestamp()],
+    )
+  }
+
+  /// Removes expired temporary grants and returns them.
+  pub fn sweep_expired(&self) -> anyhow::Result<Vec<TemporaryGrant>> {
+    let now = Utc::now().timestamp();
+    let expired = self.transaction(|conn| {
+      let expired = Self::read_grants(
+        conn,
+        "SELECT id, chat_id, user_id, flags, granted_by, expires_at FROM temporary_grants
+               WHERE expires_at <= ?1",
+        params![now],
+      )?;
+
+      if !expired.is_empty() {
+        conn.execute(
+          "DELETE FROM temporary_grants WHERE expires_at <= ?1",
+          params![now],
+        )?;
+      }
+
+      Ok(expired)
+    })?;
+
+    if !expired.is_empty() {
+      log::debug!("swept {} expired temporary grants", expired.len());
+    }
+
+    Ok(expired)
   }
 
-  pub fn has(&self, user_id: UserId, perm: Permission) -> anyhow::Result<bool> {
-    let has_perm = self.get(user_id)?.contains(perm);
+  /// Also strips `perm` from temporary grants in the scope, dropping grants
+  /// that are left without flags.
+  pub fn revoke(&self, scope: Scope, user_id: UserId, perm: Permission) -> anyhow::Result<()> {
+    let current = self.transaction(|conn| {
+      let current = Self::resolve_in(conn, scope, user_id)?;
+
+      conn.execute(
+        "INSERT INTO permissions (chat_id, user_id, flags)
+               VALUES (?1, ?2, ~?3 & COALESCE(
+                 (SELECT flags FROM permissions WHERE chat_id = 0 AND user_id = ?2), 0))
+               ON CONFLICT(chat_id, user_id) DO UPDATE SET flags = flags & ~?3",
+        params![scope.key(), user_id.0, perm.bits()],
+      )?;
+      conn.execute(
+        "UPDATE temporary_grants SET flags = flags & ~?3
+               WHERE chat_id = ?1 AND user_id = ?2 AND (flags & ?3) != 0",
+        params![scope.key(), user_id.0, perm.bits()],
+      )?;
+      conn.execute(
+        "DELETE FROM temporary_grants WHERE chat_id = ?1 AND user_id = ?2 AND flags = 0",
+        params![scope.key(), user_id.0],
+      )?;
+
+      Ok(current.map_or(Permission::NONE, |(_, perm)| perm))
+    })?;
 
     log::trace!(
-      "check if user {} has permission {:?}: {}",
-      user_id,
+      "revoke permission {:?} from user {} in {}, previous {:?}",
       perm,
-      has_perm
+      user_id,
+      scope,
+      current
     );
 
-    Ok(has_perm)
+    Ok(())
   }
 
-  pub fn can(&self, user_id: UserId, perm: Permission) -> anyhow::Result<bool> {
-    let can_access = self.get(user_id)?.level() >= perm.level();
+  /// Whether the user's level in the scope is at least that of `perm`.
+  /// Levels are hierarchical, so an owner can do whatever an admin can.
+  pub fn can(&self, scope: Scope, user_id: UserId, perm: Permission) -> anyhow::Result<bool> {
+    let can_access = self.get(scope, user_id)?.level() >= perm.level();
 
     log::trace!(
-      "check if user {} can access level {:?}: {}",
+      "check if user {} can access level {:?} in {}: {}",
       user_id,
       perm,
+      scope,
       can_access
     );
 
     Ok(can_access)
   }
 
-  pub fn perm_iter(&self) -> anyhow::Result<Vec<(UserId, Permission)>> {
+  pub fn perm_iter(&self, scope: Scope) -> anyhow::Result<Vec<(UserId, Permission)>> {
     let conn = self.db.get()?;
-    let mut stmt = conn.prepare("SELECT user_id, flags FROM permissions")?;
+    Self::perm_iter_in(&conn, scope)
+  }
+
+  fn perm_iter_in(conn: &Connection, scope: Scope) -> anyhow::Result<Vec<(UserId, Permission)>> {
+    let mut stmt = conn.prepare("SELECT user_id, flags FROM permissions WHERE chat_id = ?1")?;
 
-    let rows = stmt.query_map([], |row| {
+    let rows = stmt.query_map(params![scope.key()], |row| {
       let _user_id: String = row.get(0)?;
       let user_id: u64 = _user_id.parse().map_err(|e| {
         rusqlite::Error::FromSqlConversionFailure(
@@ -169,28 +608,263 @@ impl PermissionManager {
     Ok(result)
   }
 
-  pub fn load_snapshot_iter(&self, snapshot: &PermissionMap) -> anyhow::Result<()> {
-    let conn = self.db.get()?;
+  /// Writes every entry of the snapshot into the scope, replacing existing
+  /// entries of the same users. Nothing is written if any insert fails.
+  pub fn load_snapshot_iter(&self, scope: Scope, snapshot: &PermissionMap) -> anyhow::Result<()> {
+    self.transaction(|conn| Self::upsert_all(conn, scope, snapshot))
+  }
+
+  fn upsert_all(
+    conn: &Connection,
+    scope: Scope,
+    snapshot: &PermissionMap,
+  ) -> anyhow::Result<()> {
+    let mut stmt = conn.prepare(
+      "INSERT INTO permissions (chat_id, user_id, flags)
+             VALUES (?1, ?2, ?3)
+             ON CONFLICT(chat_id, user_id) DO UPDATE SET flags = excluded.flags",
+    )?;
 
     for (user_id, perm) in snapshot {
-      conn.execute(
-        "INSERT INTO permissions (user_id, flags) VALUES (?1, ?2)",
-        params![user_id.0, perm.bits()],
-      )?;
+      stmt.execute(params![scope.key(), user_id.0, perm.bits()])?;
     }
 
     Ok(())
   }
 
-  pub fn snapshot(&self) -> anyhow::Result<PermissionMap> {
-    let result: PermissionMap = self.perm_iter()?.into_iter().collect();
-    log::trace!("snapshot() returned {} entries", result.len());
+  pub fn snapshot(&self, scope: Scope) -> anyhow::Result<PermissionMap> {
+    let result: PermissionMap = self.perm_iter(scope)?.into_iter().collect();
+    log::trace!("snapshot({}) returned {} entries", scope, result.len());
     Ok(result)
   }
 
-  pub fn load_snapshot(&self, snapshot: &PermissionMap) -> anyhow::Result<()> {
-    self.clear()?;
-    self.load_snapshot_iter(snapshot)?;
-    Ok(())
+  /// Replaces every entry of the scope with the snapshot in one transaction.
+  pub fn load_snapshot(&self, scope: Scope, snapshot: &PermissionMap) -> anyhow::Result<()> {
+    self.transaction(|conn| {
+      conn.execute(
+        "DELETE FROM permissions WHERE chat_id = ?1",
+        params![scope.key()],
+      )?;
+      Self::upsert_all(conn, scope, snapshot)
+    })
+  }
+
+  /// Explicit entries of every scope.
+  pub fn full_snapshot(&self) -> anyhow::Result<PermissionSnapshot> {
+    let conn = self.db.get()?;
+    let snapshot = Self::full_snapshot_in(&conn)?;
+
+    log::trace!("full snapshot has {} entries", snapshot.len());
+
+    Ok(snapshot)
+  }
+
+  fn full_snapshot_in(conn: &Connection) -> anyhow::Result<PermissionSnapshot> {
+    let mut stmt = conn.prepare("SELECT DISTINCT chat_id FROM permissions WHERE chat_id != 0")?;
+    let chats: Vec<i64> = stmt.query_map([], |row| row.get(0))?.collect::<Result<_, _>>()?;
+
+    let mut snapshot = PermissionSnapshot {
+      global: Self::perm_iter_in(conn, Scope::Global)?.into_iter().collect(),
+      ..Default::default()
+    };
+
+    for chat_id in chats {
+      let scope = Scope::from_key(chat_id);
+      snapshot
+        .chats
+        .insert(chat_id, Self::perm_iter_in(conn, scope)?.into_iter().collect());
+    }
+
+    Ok(snapshot)
+  }
+
+  /// Changes that importing `snapshot` with `mode` would make, ordered by
+  /// scope and user.
+  pub fn diff_snapshot(
+    &self,
+    snapshot: &PermissionSnapshot,
+    mode: ImportMode,
+  ) -> anyhow::Result<Vec<SnapshotChange>> {
+    Ok(Self::diff_against(&self.full_snapshot()?, snapshot, mode))
+  }
+
+  fn diff_against(
+    current: &PermissionSnapshot,
+    snapshot: &PermissionSnapshot,
+    mode: ImportMode,
+  ) -> Vec<SnapshotChange> {
+    let mut changes = Vec::new();
+
+    for (scope, map) in snapshot.scopes() {
+      for (user_id, perm) in map {
+        let before = current.get(scope, *user_id);
+        if before != Some(*perm) {
+          changes.push(SnapshotChange {
+            scope,
+            user_id: *user_id,
+            before,
+            after: Some(*perm),
+          });
+        }
+      }
+    }
+
+    if mode == ImportMode::Replace {
+      for (scope, map) in current.scopes() {
+        for (user_id, perm) in map {
+          if snapshot.get(scope, *user_id).is_none() {
+            changes.push(SnapshotChange {
+              scope,
+              user_id: *user_id,
+              before: Some(*perm),
+              after: None,
+            });
+          }
+        }
+      }
+    }
+
+    changes.sort_by_key(|change| (change.scope.key(), change.user_id.0));
+
+    changes
+  }
+
+  /// Imports a snapshot in a single transaction, returning the changes made.
+  /// Nothing is written if any statement fails.
+  pub fn import_snapshot(
+    &self,
+    snapshot: &PermissionSnapshot,
+    mode: ImportMode,
+  ) -> anyhow::Result<Vec<SnapshotChange>> {
+    anyhow::ensure!(
+      !snapshot.chats.contains_key(&Scope::Global.key()),
+      "snapshot uses chat id 0, which is reserved for global entries"
+    );
+
+    let changes = self.transaction(|conn| {
+      let changes = Self::diff_against(&Self::full_snapshot_in(conn)?, snapshot, mode);
+
+      if mode == ImportMode::Replace {
+        conn.execute("DELETE FROM permissions", [])?;
+      }
+
+      for (scope, map) in snapshot.scopes() {
+        Self::upsert_all(conn, scope, map)?;
+      }
+
+      Ok(changes)
+    })?;
+
+    log::debug!(
+      "imported {} permission entries ({:?}), {} changes",
+      snapshot.len(),
+      mode,
+      changes.len()
+    );
+
+    Ok(changes)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const ALICE: UserId = UserId(1);
+  const CHAT: Scope = Scope::Chat(ChatId(-100));
+
+  fn manager() -> PermissionManager {
+    let pool = Pool::builder()
+      .max_size(1)
+      .build(SqliteConnectionManager::memory())
+      .unwrap();
+    PermissionManager::new(Arc::new(pool), Permission::NONE).unwrap()
+  }
+
+  #[test]
+  fn grant_adds_flags_to_the_entry() {
+    let pm = manager();
+
+    pm.grant(Scope::Global, ALICE, Permission::USER).unwrap();
+    pm.grant(Scope::Global, ALICE, Permission::ADMIN).unwrap();
+
+    assert_eq!(
+      pm.lookup(Scope::Global, ALICE).unwrap(),
+      Some(Permission::USER | Permission::ADMIN)
+    );
+  }
+
+  #[test]
+  fn grant_in_chat_starts_from_the_global_entry() {
+    let pm = manager();
+
+    pm.set(Scope::Global, ALICE, Permission::USER).unwrap();
+    pm.grant(CHAT, ALICE, Permission::ADMIN).unwrap();
+
+    assert_eq!(pm.lookup(CHAT, ALICE).unwrap(), Some(Permission::USER | Permission::ADMIN));
+    assert_eq!(pm.lookup(Scope::Global, ALICE).unwrap(), Some(Permission::USER));
+  }
+
+  #[test]
+  fn revoke_removes_flags_from_the_entry() {
+    let pm = manager();
+
+    pm.set(Scope::Global, ALICE, Permission::USER | Permission::ADMIN).unwrap();
+    pm.revoke(Scope::Global, ALICE, Permission::ADMIN).unwrap();
+
+    assert_eq!(pm.lookup(Scope::Global, ALICE).unwrap(), Some(Permission::USER));
+  }
+
+  #[test]
+  fn revoke_in_chat_starts_from_the_global_entry() {
+    let pm = manager();
+
+    pm.set(Scope::Global, ALICE, Permission::USER | Permission::ADMIN).unwrap();
+    pm.revoke(CHAT, ALICE, Permission::ADMIN).unwrap();
+
+    assert_eq!(pm.lookup(CHAT, ALICE).unwrap(), Some(Permission::USER));
+    assert_eq!(
+      pm.lookup(Scope::Global, ALICE).unwrap(),
+      Some(Permission::USER | Permission::ADMIN)
+    );
+  }
+
+  #[test]
+  fn revoke_strips_only_the_named_flags_from_temporary_grants() {
+    let pm = manager();
+    let hour = Duration::from_secs(3600);
+
+    pm.grant_for(CHAT, ALICE, Permission::USER | Permission::ADMIN, hour, None).unwrap();
+    pm.grant_for(CHAT, ALICE, Permission::ADMIN, hour, None).unwrap();
+    pm.revoke(CHAT, ALICE, Permission::ADMIN).unwrap();
+
+    let grants = pm.temporary_grants(CHAT, ALICE).unwrap();
+    assert_eq!(grants.len(), 1);
+    assert_eq!(grants[0].perm, Permission::USER);
+    assert_eq!(pm.get(CHAT, ALICE).unwrap(), Permission::USER);
+  }
+
+  #[test]
+  fn global_temporary_grants_do_not_lift_chat_entries() {
+    let pm = manager();
+    let hour = Duration::from_secs(3600);
+
+    pm.grant_for(Scope::Global, ALICE, Permission::ADMIN, hour, None).unwrap();
+    assert_eq!(pm.get(CHAT, ALICE).unwrap(), Permission::ADMIN);
+
+    pm.set(CHAT, ALICE, Permission::NONE).unwrap();
+    assert_eq!(pm.get(CHAT, ALICE).unwrap(), Permission::NONE);
+    assert_eq!(pm.get(Scope::Global, ALICE).unwrap(), Permission::ADMIN);
+  }
+
+  #[test]
+  fn import_rejects_chat_zero() {
+    let pm = manager();
+
+    let mut snapshot = PermissionSnapshot::default();
+    snapshot.chats.insert(0, PermissionMap::from([(ALICE, Permission::OWNER)]));
+
+    assert!(pm.import_snapshot(&snapshot, ImportMode::Merge).is_err());
+    assert_eq!(pm.lookup(Scope::Global, ALICE).unwrap(), None);
   }
 }
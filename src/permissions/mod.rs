@@ -1,2 +1,6 @@
+pub mod admins;
+pub mod blocklist;
 pub mod manager;
+pub mod roles;
+pub mod sweeper;
 pub mod types;
@@ -0,0 +1,379 @@
+use rusqlite::{params, OptionalExtension};
+use std::sync::Arc;
+
+use r2d2::Pool;
+use r2d2_sqlite::SqliteConnectionManager;
+
+use teloxide::prelude::UserId;
+
+use super::types::{Permission, Scope};
+
+/// Built-in roles mirroring the permission levels, with their seeded
+/// capabilities. Their capability sets can be extended but the roles
+/// themselves cannot be deleted.
+pub const BUILTIN_ROLES: &[(&str, &str)] = &[
+  ("user", "level.user"),
+  ("admin", "level.admin"),
+  ("owner", "*"),
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Role {
+  pub name: String,
+  pub builtin: bool,
+  pub capabilities: Vec<String>,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum RoleError {
+  #[error("role {0} does not exist")]
+  UnknownRole(String),
+
+  #[error("role {0} already exists")]
+  RoleExists(String),
+
+  #[error("role {0} is built in")]
+  Builtin(String),
+
+  #[error("invalid role name '{0}'")]
+  InvalidName(String),
+}
+
+/// Returns the built-in role matching a permission level, if any.
+pub fn builtin_role_of(perm: Permission) -> Option<&'static str> {
+  match perm.level() {
+    3 => Some("owner"),
+    2 => Some("admin"),
+    1 => Some("user"),
+    _ => None,
+  }
+}
+
+/// Whether a granted capability covers a command's capability.
+///
+/// `*` covers everything, `plugin.*` covers every capability of a plugin and
+/// `level.<name>` covers every command declared at or below that level.
+pub fn capability_covers(
+  granted: &str,
+  required: &str,
+  perm: Permission,
+) -> bool {
+  if granted == "*" || granted == required {
+    return true;
+  }
+
+  if let Some(plugin) = granted.strip_suffix(".*") {
+    return required
+      .strip_prefix(plugin)
+      .is_some_and(|rest| rest.starts_with('.'));
+  }
+
+  let level = match granted {
+    "level.user" => Permission::USER.level(),
+    "level.admin" => Permission::ADMIN.level(),
+    "level.owner" => Permission::OWNER.level(),
+    _ => return false,
+  };
+
+  perm.level() <= level
+}
+
+#[derive(Debug, Clone)]
+pub struct RoleManager {
+  pub db: Arc<Pool<SqliteConnectionManager>>,
+}
+
+impl RoleManager {
+  pub fn new(db: Arc<Pool<SqliteConnectionManager>>) -> anyhow::Result<Self> {
+    let mgr = Self { db };
+    mgr.init_schema()?;
+    Ok(mgr)
+  }
+
+  pub fn new_shared(db: Arc<Pool<SqliteConnectionManager>>) -> anyhow::Result<Arc<Self>> {
+    let mgr = Self::new(db)?;
+    Ok(Arc::new(mgr))
+  }
+
+  fn init_schema(&self) -> anyhow::Result<()> {
+    let conn = self.db.get()?;
+    conn.execute(
+      "CREATE TABLE IF NOT EXISTS roles (
+                name    TEXT PRIMARY KEY,
+                builtin INTEGER NOT NULL DEFAULT 0
+            )",
+      [],
+    )?;
+    conn.execute(
+      "CREATE TABLE IF NOT EXISTS role_capabilities (
+                role       TEXT NOT NULL,
+                capability TEXT NOT NULL,
+                PRIMARY KEY (role, capability)
+            )",
+      [],
+    )?;
+    conn.execute(
+      "CREATE TABLE IF NOT EXISTS user_roles (
+                chat_id INTEGER NOT NULL DEFAULT 0,
+                user_id TEXT NOT NULL,
+                role    TEXT NOT NULL,
+                PRIMARY KEY (chat_id, user_id, role)
+            )",
+      [],
+    )?;
+
+    for (name, capability) in BUILTIN_ROLES {
+      let created = conn.execute(
+        "INSERT OR IGNORE INTO roles (name, builtin) VALUES (?1, 1)",
+        params![name],
+      )?;
+
+      if created > 0 {
+        log::info!("seeding built-in role {}", name);
+        conn.execute(
+          "INSERT OR IGNORE INTO role_capabilities (role, capability) VALUES (?1, ?2)",
+          params![name, capability],
+        )?;
+      }
+    }
+
+    Ok(())
+  }
+
+  fn find(
+    &self,
+    conn: &rusqlite::Connection,
+    name: &str,
+  ) -> anyhow::Result<bool> {
+    let builtin = conn
+      .query_row(
+        "SELECT builtin FROM roles WHERE name = ?1",
+        params![name],
+        |row| row.get::<_, bool>(0),
+      )
+      .optional()?;
+
+    match builtin {
+      Some(builtin) => Ok(builtin),
+      None => Err(RoleError::UnknownRole(name.to_string()).into()),
+    }
+  }
+
+  pub fn roles(&self) -> anyhow::Result<Vec<Role>> {
+    let conn = self.db.get()?;
+    let mut stmt = conn.prepare("SELECT name, builtin FROM roles ORDER BY builtin DESC, name")?;
+
+    let names: Vec<(String, bool)> = stmt
+      .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+      .collect::<Result<_, _>>()?;
+
+    let mut roles = Vec::new();
+    for (name, builtin) in names {
+      roles.push(Role {
+        capabilities: self.capabilities(&name)?,
+        name,
+        builtin,
+      });
+    }
+
+    Ok(roles)
+  }
+
+  pub fn role(&self, name: &str) -> anyhow::Result<Role> {
+    let conn = self.db.get()?;
+    let builtin = self.find(&conn, name)?;
+
+    Ok(Role {
+      name: name.to_string(),
+      builtin,
+      capabilities: self.capabilities(name)?,
+    })
+  }
+
+  pub fn capabilities(&self, role: &str) -> anyhow::Result<Vec<String>> {
+    let conn = self.db.get()?;
+    let mut stmt = conn.prepare(
+      "SELECT capability FROM role_capabilities WHERE role = ?1 ORDER BY capability",
+    )?;
+
+    let caps = stmt
+      .query_map(params![role], |row| row.get(0))?
+      .collect::<Result<_, _>>()?;
+
+    Ok(caps)
+  }
+
+  pub fn create(&self, name: &str) -> anyhow::Result<()> {
+    if name.is_empty() || name.contains(char::is_whitespace) {
+      return Err(RoleError::InvalidName(name.to_string()).into());
+    }
+
+    let conn = self.db.get()?;
+    let created = conn.execute(
+      "INSERT OR IGNORE INTO roles (name, builtin) VALUES (?1, 0)",
+      params![name],
+    )?;
+
+    if created == 0 {
+      return Err(RoleError::RoleExists(name.to_string()).into());
+    }
+
+    log::trace!("created role {}", name);
+
+    Ok(())
+  }
+
+  pub fn delete(&self, name: &str) -> anyhow::Result<()> {
+    let conn = self.db.get()?;
+    if self.find(&conn, name)? {
+      return Err(RoleError::Builtin(name.to_string()).into());
+    }
+
+    conn.execute("DELETE FROM user_roles WHERE role = ?1", params![name])?;
+    conn.execute("DELETE FROM role_capabilities WHERE role = ?1", params![name])?;
+    conn.execute("DELETE FROM roles WHERE name = ?1", params![name])?;
+
+    log::trace!("deleted role {}", name);
+
+    Ok(())
+  }
+
+  pub fn allow(
+    &self,
+    role: &str,
+    capability: &str,
+  ) -> anyhow::Result<()> {
+    let conn = self.db.get()?;
+    self.find(&conn, role)?;
+    conn.execute(
+      "INSERT OR IGNORE INTO role_capabilities (role, capability) VALUES (?1, ?2)",
+      params![role, capability],
+    )?;
+
+    log::trace!("allowed capability {} for role {}", capability, role);
+
+    Ok(())
+  }
+
+  pub fn deny(
+    &self,
+    role: &str,
+    capability: &str,
+  ) -> anyhow::Result<()> {
+    let conn = self.db.get()?;
+    if self.find(&conn, role)? && role == "owner" && capability == "*" {
+      return Err(RoleError::Builtin(role.to_string()).into());
+    }
+
+    conn.execute(
+      "DELETE FROM role_capabilities WHERE role = ?1 AND capability = ?2",
+      params![role, capability],
+    )?;
+
+    log::trace!("denied capability {} for role {}", capability, role);
+
+    Ok(())
+  }
+
+  pub fn assign(
+    &self,
+    scope: Scope,
+    user_id: UserId,
+    role: &str,
+  ) -> anyhow::Result<()> {
+    let conn = self.db.get()?;
+    self.find(&conn, role)?;
+    conn.execute(
+      "INSERT OR IGNORE INTO user_roles (chat_id, user_id, role) VALUES (?1, ?2, ?3)",
+      params![scope.key(), user_id.0, role],
+    )?;
+
+    log::trace!("assigned role {} to user {} in {}", role, user_id, scope);
+
+    Ok(())
+  }
+
+  pub fn unassign(
+    &self,
+    scope: Scope,
+    user_id: UserId,
+    role: &str,
+  ) -> anyhow::Result<()> {
+    let conn = self.db.get()?;
+    conn.execute(
+      "DELETE FROM user_roles WHERE chat_id = ?1 AND user_id = ?2 AND role = ?3",
+      params![scope.key(), user_id.0, role],
+    )?;
+
+    log::trace!("unassigned role {} from user {} in {}", role, user_id, scope);
+
+    Ok(())
+  }
+
+  /// Roles assigned to the user in a scope, including global assignments.
+  pub fn roles_of(
+    &self,
+    scope: Scope,
+    user_id: UserId,
+  ) -> anyhow::Result<Vec<String>> {
+    let conn = self.db.get()?;
+    let mut stmt = conn.prepare(
+      "SELECT DISTINCT role FROM user_roles
+             WHERE user_id = ?1 AND chat_id IN (0, ?2)
+             ORDER BY role",
+    )?;
+
+    let roles = stmt
+      .query_map(params![user_id.0, scope.key()], |row| row.get(0))?
+      .collect::<Result<_, _>>()?;
+
+    Ok(roles)
+  }
+
+  /// Whether the user may run a command needing `capability`, declared at
+  /// `required`, given their permission `perm` in the scope. The built-in
+  /// role matching `perm` counts as assigned. Commands open to everyone
+  /// (`required` of level 0) need no capability.
+  pub fn grants(
+    &self,
+    scope: Scope,
+    user_id: UserId,
+    perm: Permission,
+    capability: &str,
+    required: Permission,
+  ) -> anyhow::Result<bool> {
+    if required.level() == 0 {
+      return Ok(true);
+    }
+
+    let mut roles = self.roles_of(scope, user_id)?;
+    if let Some(builtin) = builtin_role_of(perm) {
+      roles.push(builtin.to_string());
+    }
+
+    for role in &roles {
+      for granted in self.capabilities(role)? {
+        if capability_covers(&granted, capability, required) {
+          log::trace!(
+            "capability {} of role {} covers {} for user {}",
+            granted,
+            role,
+            capability,
+            user_id
+          );
+          return Ok(true);
+        }
+      }
+    }
+
+    log::trace!(
+      "no role of user {} in {} covers {} ({:?})",
+      user_id,
+      scope,
+      capability,
+      required
+    );
+
+    Ok(false)
+  }
+}
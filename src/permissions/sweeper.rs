@@ -0,0 +1,98 @@
+use std::sync::Weak;
+use std::time::Duration;
+use tokio::sync::Mutex;
+
+use teloxide::payloads::SendMessageSetters;
+use teloxide::prelude::{ChatId, Requester};
+use teloxide::types::ParseMode;
+
+use crate::bot::context::Context;
+use crate::utils::{env, style};
+
+use super::types::TemporaryGrant;
+
+/// Removes expired temporary grants every `interval` and tells the owner
+/// about each one that lapsed. Stops once the context is dropped or the bot
+/// shuts down.
+pub async fn run(
+  ctx: Weak<Mutex<Context>>,
+  interval: Duration,
+) {
+  let mut ticker = tokio::time::interval(interval.max(Duration::from_secs(1)));
+
+  let shutdown = match ctx.upgrade() {
+    Some(ctx) => ctx.lock().await.shutdown.clone(),
+    None => return,
+  };
+
+  loop {
+    tokio::select! {
+      _ = ticker.tick() => {}
+      _ = shutdown.triggered() => {
+        log::debug!("grant sweeper stopped: shutting down");
+        return;
+      }
+    }
+
+    let (perm_mgr, bot) = match ctx.upgrade() {
+      Some(ctx) => {
+        let ctx = ctx.lock().await;
+        (ctx.perm_mgr.clone(), ctx.bot.clone())
+      }
+      None => {
+        log::debug!("grant sweeper stopped: context dropped");
+        return;
+      }
+    };
+
+    let expired = match perm_mgr.sweep_expired() {
+      Ok(expired) => expired,
+      Err(err) => {
+        log::error!("failed to sweep expired grants: {:?}", err);
+        continue;
+      }
+    };
+
+    for grant in expired {
+      if let Err(err) = notify(&bot, ctx.clone(), &grant).await {
+        log::warn!("failed to report lapsed grant {}: {:?}", grant.id, err);
+      }
+    }
+  }
+}
+
+async fn notify(
+  bot: &teloxide::Bot,
+  ctx: Weak<Mutex<Context>>,
+  grant: &TemporaryGrant,
+) -> anyhow::Result<()> {
+  let owner_id = env::get_owner_id().await?;
+  let style = style::get_style(ctx).await;
+
+  let text = format!(
+    "{} <b>Temporary Grant Lapsed</b>\n\
+    {} <b>User:</b> <code>{}</code>\n\
+    {} <b>Scope:</b> {}\n\
+    {} <b>Permission:</b> <code>{:?}</code>\n\
+    {} <b>Granted by:</b> {}\n",
+    style.info(),
+    style.info(),
+    grant.user_id,
+    style.info(),
+    grant.scope,
+    style.info(),
+    grant.perm,
+    style.info(),
+    grant
+      .granted_by
+      .map(|id| format!("<code>{}</code>", id))
+      .unwrap_or_else(|| "unknown".to_string()),
+  );
+
+  bot
+    .send_message(ChatId::from(owner_id), text)
+    .parse_mode(ParseMode::Html)
+    .await?;
+
+  Ok(())
+}
@@ -1,11 +1,155 @@
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 
 use bitflags::bitflags;
-use std::collections::HashMap;
-use teloxide::prelude::UserId;
+use std::collections::{BTreeMap, HashMap};
+use teloxide::prelude::{ChatId, UserId};
 
 pub type PermissionMap = HashMap<UserId, Permission>;
 
+/// Where a permission entry applies.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash, Serialize, Deserialize)]
+pub enum Scope {
+  /// Applies in every chat unless the chat has its own entry.
+  Global,
+
+  /// Applies only in the given chat.
+  Chat(ChatId),
+}
+
+impl Scope {
+  /// Value of the `chat_id` column; Telegram never uses 0 as a chat id, so it
+  /// stands for the global scope.
+  pub fn key(&self) -> i64 {
+    match self {
+      Scope::Global => 0,
+      Scope::Chat(chat_id) => chat_id.0,
+    }
+  }
+
+  pub fn from_key(key: i64) -> Self {
+    match key {
+      0 => Scope::Global,
+      id => Scope::Chat(ChatId(id)),
+    }
+  }
+}
+
+/// Every explicit permission entry, as exported by `/pmexport`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PermissionSnapshot {
+  pub global: PermissionMap,
+
+  /// Entries of individual chats, keyed by chat id.
+  #[serde(default)]
+  pub chats: BTreeMap<i64, PermissionMap>,
+}
+
+impl PermissionSnapshot {
+  pub fn scopes(&self) -> impl Iterator<Item = (Scope, &PermissionMap)> {
+    std::iter::once((Scope::Global, &self.global)).chain(
+      self
+        .chats
+        .iter()
+        .map(|(chat_id, map)| (Scope::Chat(ChatId(*chat_id)), map)),
+    )
+  }
+
+  pub fn get(
+    &self,
+    scope: Scope,
+    user_id: UserId,
+  ) -> Option<Permission> {
+    match scope {
+      Scope::Global => self.global.get(&user_id).copied(),
+      Scope::Chat(chat_id) => self.chats.get(&chat_id.0)?.get(&user_id).copied(),
+    }
+  }
+
+  pub fn len(&self) -> usize {
+    self.scopes().map(|(_, map)| map.len()).sum()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.len() == 0
+  }
+}
+
+/// How an imported snapshot combines with the existing entries.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImportMode {
+  /// Imported entries overwrite matching ones, others are kept.
+  Merge,
+
+  /// The snapshot becomes the complete set of entries.
+  Replace,
+}
+
+/// One entry that an import adds, changes or removes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SnapshotChange {
+  pub scope: Scope,
+  pub user_id: UserId,
+  pub before: Option<Permission>,
+  pub after: Option<Permission>,
+}
+
+/// A permission granted on top of a user's entry until it expires.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct TemporaryGrant {
+  pub id: i64,
+  pub scope: Scope,
+  pub user_id: UserId,
+  pub perm: Permission,
+  pub granted_by: Option<UserId>,
+  pub expires_at: DateTime<Utc>,
+}
+
+/// Replaces a command's declared permission within a scope.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]
+pub enum CommandOverride {
+  /// The command requires this permission instead.
+  Require(Permission),
+
+  /// The command is ignored for everyone.
+  Disabled,
+}
+
+impl CommandOverride {
+  /// Value of the `flags` column; `NULL` marks a disabled command.
+  pub fn bits(&self) -> Option<u32> {
+    match self {
+      CommandOverride::Require(perm) => Some(perm.bits()),
+      CommandOverride::Disabled => None,
+    }
+  }
+
+  pub fn from_bits(bits: Option<u32>) -> Self {
+    match bits {
+      Some(bits) => CommandOverride::Require(Permission::from_bits_truncate(bits)),
+      None => CommandOverride::Disabled,
+    }
+  }
+}
+
+impl std::fmt::Display for CommandOverride {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      CommandOverride::Require(perm) => write!(f, "{:?}", perm),
+      CommandOverride::Disabled => write!(f, "disabled"),
+    }
+  }
+}
+
+impl std::fmt::Display for Scope {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      Scope::Global => write!(f, "global"),
+      Scope::Chat(chat_id) => write!(f, "chat {}", chat_id),
+    }
+  }
+}
+
 bitflags! {
   #[derive(Debug, PartialEq, Eq, Clone, Copy, Hash, Serialize, Deserialize)]
   pub struct Permission: u32 {
@@ -3,141 +3,225 @@ use tokio::sync::Mutex;
 
 use indexmap::IndexMap;
 
-use teloxide::payloads::SendMessageSetters;
+use teloxide::net::Download;
+use teloxide::payloads::{SendDocumentSetters, SendMessageSetters};
 use teloxide::prelude::Requester;
-use teloxide::types::{Message, UserId};
+use teloxide::types::{ChatId, InputFile, Message, UserId};
 use teloxide::Bot;
 
-use crate::bot::command::{self, ArgMetadata, ArgRequirement, CommandMetadata, ReplyRequirement};
-use crate::permissions::types::Permission;
+use crate::bot::args::ArgKind;
+use crate::bot::command::{
+  self, ArgMetadata, ArgRequirement, CommandMetadata, OptMetadata, ReplyRequirement,
+};
+use crate::audit::manager::{AuditEntry, AuditFilter, AuditLog};
+use crate::permissions::blocklist::BlockTarget;
+use crate::permissions::manager::PermissionManager;
+use crate::permissions::types::{
+  CommandOverride, ImportMode, Permission, PermissionSnapshot, Scope, SnapshotChange,
+};
+use crate::settings::manager::SettingsManager;
+use crate::utils::parsers;
 use crate::plugins::core::CoreError;
 
 use crate::{
-  bot::{context, handler, plugin},
+  bot::{context, dispatcher, handler, plugin},
   error,
-  utils::{parsers, style},
+  utils::{formatter, style},
 };
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum PermissionEvent {
-  Grant,
+  /// Grants the permission, for the given time only if there is one.
+  Grant(Option<std::time::Duration>),
   Revoke,
   Set,
   Reset,
 }
 
+/// The current chat, or the global scope when `--global` is given.
+fn scope_of(
+  msg: &Message,
+  cmd: &command::Command,
+) -> Scope {
+  if cmd.values.flag("global") {
+    Scope::Global
+  } else {
+    Scope::Chat(msg.chat.id)
+  }
+}
+
+fn global_opt() -> OptMetadata {
+  OptMetadata::new(
+    "global".to_string(),
+    Some('g'),
+    "Apply to every chat instead of the current one".to_string(),
+    None,
+  )
+}
+
+/// Rejects changes to the global scope from anyone but a global owner, so
+/// owning a single chat is not enough to affect every chat.
+fn require_global_owner(
+  pm: &PermissionManager,
+  msg: &Message,
+) -> anyhow::Result<()> {
+  let is_owner = match &msg.from {
+    Some(user) => pm.can(Scope::Global, user.id, Permission::OWNER)?,
+    None => false,
+  };
+
+  if !is_owner {
+    return Err(
+      CoreError::PermissionDenied("global changes require a global owner".to_string()).into(),
+    );
+  }
+
+  Ok(())
+}
+
+fn describe_perm(perm: Option<Permission>) -> String {
+  match perm {
+    Some(perm) => format!("{:?}", perm),
+    None => "none".to_string(),
+  }
+}
+
+/// Appends to the audit log. Failing to audit is logged but does not undo
+/// or fail the change being audited.
+async fn record_audit(
+  audit: &AuditLog,
+  entry: AuditEntry,
+) {
+  if let Err(err) = audit.record(entry) {
+    log::error!("failed to write audit entry: {:?}", err);
+  }
+}
+
 async fn handle_perm_event(
   _bot: Bot,
   _msg: Message,
   _weak_ctx: Weak<Mutex<context::Context>>,
   _event: PermissionEvent,
+  _scope: Scope,
   _perm: Option<Permission>,
   _user_id: Option<UserId>,
 ) -> anyhow::Result<()> {
   let _ctx = match _weak_ctx.upgrade() {
     Some(ctx) => ctx,
-    None => {
-      return Err(
-        error::emit(
-          Some(_bot.clone()),
-          Some(_msg.clone()),
-          error::Error::ContextDisposed,
-        )
-        .await,
-      )
-    }
+    None => return Err(error::Error::ContextDisposed.into()),
   };
 
   let _style = style::get_style(_weak_ctx.clone()).await;
 
-  let _ctx_guard = _ctx.lock().await;
-  let _pm_guard = _ctx_guard.perm_mgr.lock().await;
+  let (_pm, _audit) = {
+    let _ctx_guard = _ctx.lock().await;
+    (_ctx_guard.perm_mgr.clone(), _ctx_guard.audit.clone())
+  };
 
   let _perm_needed: bool;
+  let mut _expires_at = None;
+
+  if _scope == Scope::Global {
+    require_global_owner(&_pm, &_msg)?;
+  }
 
   let _user_id = match _user_id {
     Some(id) => id,
-    None => {
-      return Err(
-        error::emit(
-          Some(_bot.clone()),
-          Some(_msg.clone()),
-          CoreError::OptionNotSpecified("user_id".to_string()),
-        )
-        .await,
-      );
-    }
+    None => return Err(CoreError::OptionNotSpecified("user_id".to_string()).into()),
   };
 
+  let _before = _pm.lookup(_scope, _user_id)?;
+
   match _event {
-    PermissionEvent::Grant => {
+    PermissionEvent::Grant(_duration) => {
       _perm_needed = true;
 
       if let Some(_perm) = _perm {
-        _pm_guard.grant(_user_id, _perm)?;
+        match _duration {
+          Some(_duration) => {
+            let _granted_by = _msg.from.as_ref().map(|user| user.id);
+            _expires_at = Some(_pm.grant_for(_scope, _user_id, _perm, _duration, _granted_by)?);
+          }
+          None => _pm.grant(_scope, _user_id, _perm)?,
+        }
       } else {
-        return Err(
-          error::emit(
-            Some(_bot.clone()),
-            Some(_msg.clone()),
-            CoreError::OptionNotSpecified("permission".to_string()),
-          )
-          .await,
-        );
+        return Err(CoreError::OptionNotSpecified("permission".to_string()).into());
       }
     }
     PermissionEvent::Revoke => {
       _perm_needed = true;
 
       if let Some(_perm) = _perm {
-        _pm_guard.revoke(_user_id, _perm)?;
+        _pm.revoke(_scope, _user_id, _perm)?;
       } else {
-        return Err(
-          error::emit(
-            Some(_bot.clone()),
-            Some(_msg.clone()),
-            CoreError::OptionNotSpecified("permission".to_string()),
-          )
-          .await,
-        );
+        return Err(CoreError::OptionNotSpecified("permission".to_string()).into());
       }
     }
     PermissionEvent::Set => {
       _perm_needed = true;
 
       if let Some(_perm) = _perm {
-        _pm_guard.set(_user_id, _perm)?;
+        _pm.set(_scope, _user_id, _perm)?;
       } else {
-        return Err(
-          error::emit(
-            Some(_bot.clone()),
-            Some(_msg.clone()),
-            CoreError::OptionNotSpecified("permission".to_string()),
-          )
-          .await,
-        );
+        return Err(CoreError::OptionNotSpecified("permission".to_string()).into());
       }
     }
     PermissionEvent::Reset => {
       _perm_needed = false;
 
-      _pm_guard.reset(_user_id)?;
+      _pm.reset(_scope, _user_id)?;
     }
   }
 
   let _action = match _event {
-    PermissionEvent::Grant => "granted",
+    PermissionEvent::Grant(_) => "granted",
     PermissionEvent::Revoke => "revoked",
     PermissionEvent::Set => "setted",
     PermissionEvent::Reset => "resetted",
   };
 
+  let (_audit_action, _command) = match _event {
+    PermissionEvent::Grant(_) => ("permission.grant", "pmgrant"),
+    PermissionEvent::Revoke => ("permission.revoke", "pmrevoke"),
+    PermissionEvent::Set => ("permission.set", "pmset"),
+    PermissionEvent::Reset => ("permission.reset", "pmreset"),
+  };
+
+  let _after = match (_expires_at, _perm) {
+    (Some(at), Some(perm)) => format!(
+      "{} + {:?} until {}",
+      describe_perm(_before),
+      perm,
+      at.format("%Y-%m-%d %H:%M:%S UTC")
+    ),
+    _ => describe_perm(_pm.lookup(_scope, _user_id)?),
+  };
+
+  record_audit(
+    &_audit,
+    AuditEntry {
+      chat_id: Some(_msg.chat.id),
+      actor: _msg.from.as_ref().map(|user| user.id),
+      action: _audit_action.to_string(),
+      target: Some(_user_id),
+      command: Some(_command.to_string()),
+      before: Some(describe_perm(_before)),
+      after: Some(_after),
+      detail: Some(_scope.to_string()),
+      ..Default::default()
+    },
+  )
+  .await;
+
   let _msg_text = match _event {
-    PermissionEvent::Grant | PermissionEvent::Revoke | PermissionEvent::Set => {
-      let action_verb = match _event {
-        PermissionEvent::Grant => "granted",
-        PermissionEvent::Revoke => "revoked",
-        PermissionEvent::Set => "set",
+    PermissionEvent::Grant(_) | PermissionEvent::Revoke | PermissionEvent::Set => {
+      let action_verb = match (_event, _expires_at) {
+        (PermissionEvent::Grant(_), Some(at)) => {
+          format!("granted until {}", at.format("%Y-%m-%d %H:%M:%S UTC"))
+        }
+        (PermissionEvent::Grant(_), None) => "granted".to_string(),
+        (PermissionEvent::Revoke, _) => "revoked".to_string(),
+        (PermissionEvent::Set, _) => "set".to_string(),
         _ => unreachable!(),
       };
 
@@ -148,12 +232,15 @@ async fn handle_perm_event(
       format!(
         "{} <b>Permission Update</b>\n\
       {} <b>User:</b> <code>{}</code>\n\
+      {} <b>Scope:</b> {}\n\
       {} <b>Permission:</b> <code>{}</code>\n\
       {} <b>Action:</b> {}\n",
         _style.info(),
         _style.info(),
         _user_id,
         _style.info(),
+        _scope,
+        _style.info(),
         perm_name,
         _style.info(),
         action_verb,
@@ -163,10 +250,13 @@ async fn handle_perm_event(
     PermissionEvent::Reset => format!(
       "{} <b>Permissions Reset</b>\n\
     {} <b>User:</b> <code>{}</code>\n\
+    {} <b>Scope:</b> {}\n\
     {} All permissions have been reset.\n",
+      _style.info(),
       _style.info(),
       _user_id,
       _style.info(),
+      _scope,
       _style.info(),
     ),
   };
@@ -179,96 +269,17 @@ async fn handle_perm_event(
   Ok(())
 }
 
-async fn parse_user_id_and_perm(
-  _bot: &Bot,
-  _msg: &Message,
-  _cmd: &command::Command,
-  _require_userid: bool,
-  _require_perm: bool,
-) -> anyhow::Result<(Option<UserId>, Option<Permission>)> {
-  let _user_id = if let Some(reply) = &_msg.reply_to_message() {
-    reply.from.as_ref().map(|u| u.id)
-  } else if _cmd.args.is_empty() {
-    if _require_userid {
-      return Err(
-        error::emit(
-          Some(_bot.clone()),
-          Some(_msg.clone()),
-          CoreError::OptionNotSpecified("user_id".to_string()),
-        )
-        .await,
-      );
-    }
-    None
-  } else {
-    match parsers::parse_uid(&_cmd.args[0]).await {
-      Ok(uid) => Some(uid),
-      Err(_) => {
-        if _require_userid {
-          return Err(
-            error::emit(
-              Some(_bot.clone()),
-              Some(_msg.clone()),
-              CoreError::InvalidOption("user_id".to_string()),
-            )
-            .await,
-          );
-        }
-        None
-      }
-    }
-  };
-
-  let _perm = if _require_perm {
-    let perm_str = if let Some(_reply) = &_msg.reply_to_message() {
-      _cmd.args.get(0)
-    } else {
-      let index = if _require_userid { 1 } else { 0 };
-      _cmd.args.get(index)
-    };
-
-    let perm_str = match perm_str {
-      Some(p) => p,
-      None => {
-        return Err(
-          error::emit(
-            Some(_bot.clone()),
-            Some(_msg.clone()),
-            CoreError::OptionNotSpecified("perm".to_string()),
-          )
-          .await,
-        );
-      }
-    };
-
-    match parsers::parse_permission(perm_str).await {
-      Ok(perm) => Some(perm),
-      Err(_) => {
-        return Err(
-          error::emit(
-            Some(_bot.clone()),
-            Some(_msg.clone()),
-            CoreError::UnknownOption(format!("perm {}", perm_str)),
-          )
-          .await,
-        );
-      }
-    }
-  } else {
-    None
-  };
-
-  Ok((_user_id, _perm))
-}
-
 async fn on_grant(
   bot: Bot,
   msg: Message,
   cmd: command::Command,
   ctx: Weak<Mutex<context::Context>>,
 ) -> anyhow::Result<()> {
-  let (user_id, perm) = parse_user_id_and_perm(&bot, &msg, &cmd, true, true).await?;
-  handle_perm_event(bot, msg, ctx, PermissionEvent::Grant, perm, user_id).await
+  let user_id = cmd.values.user_id("user_id");
+  let perm = cmd.values.permission("perm");
+  let scope = scope_of(&msg, &cmd);
+  let event = PermissionEvent::Grant(cmd.values.duration("duration"));
+  handle_perm_event(bot, msg, ctx, event, scope, perm, user_id).await
 }
 
 async fn on_revoke(
@@ -277,8 +288,10 @@ async fn on_revoke(
   cmd: command::Command,
   ctx: Weak<Mutex<context::Context>>,
 ) -> anyhow::Result<()> {
-  let (user_id, perm) = parse_user_id_and_perm(&bot, &msg, &cmd, true, true).await?;
-  handle_perm_event(bot, msg, ctx, PermissionEvent::Revoke, perm, user_id).await
+  let user_id = cmd.values.user_id("user_id");
+  let perm = cmd.values.permission("perm");
+  let scope = scope_of(&msg, &cmd);
+  handle_perm_event(bot, msg, ctx, PermissionEvent::Revoke, scope, perm, user_id).await
 }
 
 async fn on_set(
@@ -287,8 +300,10 @@ async fn on_set(
   cmd: command::Command,
   ctx: Weak<Mutex<context::Context>>,
 ) -> anyhow::Result<()> {
-  let (user_id, perm) = parse_user_id_and_perm(&bot, &msg, &cmd, true, true).await?;
-  handle_perm_event(bot, msg, ctx, PermissionEvent::Set, perm, user_id).await
+  let user_id = cmd.values.user_id("user_id");
+  let perm = cmd.values.permission("perm");
+  let scope = scope_of(&msg, &cmd);
+  handle_perm_event(bot, msg, ctx, PermissionEvent::Set, scope, perm, user_id).await
 }
 
 async fn on_reset(
@@ -297,8 +312,9 @@ async fn on_reset(
   cmd: command::Command,
   ctx: Weak<Mutex<context::Context>>,
 ) -> anyhow::Result<()> {
-  let (user_id, _) = parse_user_id_and_perm(&bot, &msg, &cmd, true, false).await?;
-  handle_perm_event(bot, msg, ctx, PermissionEvent::Reset, None, user_id).await
+  let user_id = cmd.values.user_id("user_id");
+  let scope = scope_of(&msg, &cmd);
+  handle_perm_event(bot, msg, ctx, PermissionEvent::Reset, scope, None, user_id).await
 }
 
 async fn on_show(
@@ -307,77 +323,105 @@ async fn on_show(
   _cmd: command::Command,
   _weak_ctx: Weak<Mutex<context::Context>>,
 ) -> anyhow::Result<()> {
-  let (_user_id, _) = parse_user_id_and_perm(&_bot, &_msg, &_cmd, false, false).await?;
+  let _user_id = _cmd.values.user_id("user_id");
 
   let _ctx = match _weak_ctx.upgrade() {
     Some(ctx) => ctx,
-    None => {
-      return Err(
-        error::emit(
-          Some(_bot.clone()),
-          Some(_msg.clone()),
-          error::Error::ContextDisposed,
-        )
-        .await,
-      )
-    }
+    None => return Err(error::Error::ContextDisposed.into()),
   };
 
   let _style = style::get_style(_weak_ctx.clone()).await;
 
-  let _ctx_guard = _ctx.lock().await;
-  let _pm_guard = _ctx_guard.perm_mgr.lock().await;
+  let (_pm, _role_mgr) = {
+    let _ctx_guard = _ctx.lock().await;
+    (_ctx_guard.perm_mgr.clone(), _ctx_guard.roles.clone())
+  };
 
-  let _pm_map = _pm_guard.snapshot()?;
+  let _scope = scope_of(&_msg, &_cmd);
+  let _default = _pm.default_for(_msg.chat.id)?;
 
   if let Some(_uid) = _user_id {
-    match _pm_map.get(&_uid) {
-      Some(perm) => {
-        let text = format!(
-          "{} <b>User ID:</b> <code>{}</code>\n{} <b>Permission:</b> <code>{:?}</code>",
-          _style.bullet(),
-          _uid.0,
-          _style.info(),
-          perm
-        );
+    let (perm, source) = match _pm.resolve(_scope, _uid)? {
+      Some((scope, perm)) => (perm, scope.to_string()),
+      None => (_default, "default".to_string()),
+    };
 
-        _bot
-          .send_message(_msg.chat.id, text)
-          .parse_mode(teloxide::types::ParseMode::Html)
-          .await?;
-      }
-      None => {
-        return Err(
-          error::emit(
-            Some(_bot.clone()),
-            Some(_msg.clone()),
-            CoreError::NotFound("permissions".to_string()),
-          )
-          .await,
-        )
-      }
+    let _roles = _role_mgr.roles_of(_scope, _uid)?;
+    let _roles = if _roles.is_empty() {
+      "none".to_string()
+    } else {
+      _roles.join(", ")
+    };
+
+    let mut text = format!(
+      "{} <b>User ID:</b> <code>{}</code>\n\
+      {} <b>Permission:</b> <code>{:?}</code> ({})\n\
+      {} <b>Roles:</b> {}",
+      _style.bullet(),
+      _uid.0,
+      _style.info(),
+      perm,
+      source,
+      _style.info(),
+      _roles
+    );
+
+    for grant in _pm.temporary_grants(_scope, _uid)? {
+      text.push_str(&format!(
+        "\n{} <b>Temporary:</b> <code>{:?}</code> ({}) until {}",
+        _style.info(),
+        grant.perm,
+        grant.scope,
+        grant.expires_at.format("%Y-%m-%d %H:%M:%S UTC")
+      ));
     }
+
+    _bot
+      .send_message(_msg.chat.id, text)
+      .parse_mode(teloxide::types::ParseMode::Html)
+      .await?;
+
     return Ok(());
   }
 
-  if _pm_map.is_empty() {
-    return Err(
-      error::emit(
-        Some(_bot.clone()),
-        Some(_msg.clone()),
-        CoreError::IsEmpty("permission map".to_string()),
-      )
-      .await,
-    );
+  let mut _entries: Vec<(UserId, Permission, Scope)> = _pm
+    .perm_iter(_scope)?
+    .into_iter()
+    .map(|(uid, perm)| (uid, perm, _scope))
+    .collect();
+
+  if _scope != Scope::Global {
+    for (uid, perm) in _pm.perm_iter(Scope::Global)? {
+      if !_entries.iter().any(|(other, _, _)| *other == uid) {
+        _entries.push((uid, perm, Scope::Global));
+      }
+    }
+  }
+
+  if _entries.is_empty() {
+    return Err(CoreError::IsEmpty("permission map".to_string()).into());
   }
 
-  let mut text = format!("{} <b>Current Permission Map:</b>\n\n", _style.bullet());
-  for (uid, perm) in _pm_map {
+  let _level = _cmd.values.permission("level");
+
+  let mut text = format!(
+    "{} <b>Current Permission Map</b> ({}):\n{} Default: <b>{:?}</b>\n\n",
+    _style.bullet(),
+    _scope,
+    _style.info(),
+    _default
+  );
+  for (uid, perm, scope) in _entries {
+    if _level.is_some_and(|level| level.level() != perm.level()) {
+      continue;
+    }
+
     text.push_str(&format!(
-      "{} User ID: <code>{}</code>\n  └─ Permission: <b>{:?}</b>\n",
+      "{} User ID: <code>{}</code>\n  └─ Permission: <b>{:?}</b> ({})\n",
       _style.info(),
       uid.0,
-      perm
+      perm,
+      scope
     ));
   }
 
@@ -389,137 +433,1533 @@ async fn on_show(
   Ok(())
 }
 
-pub struct Plugin {}
+async fn on_default(
+  _bot: Bot,
+  _msg: Message,
+  _cmd: command::Command,
+  _weak_ctx: Weak<Mutex<context::Context>>,
+) -> anyhow::Result<()> {
+  let _ctx = match _weak_ctx.upgrade() {
+    Some(ctx) => ctx,
+    None => return Err(error::Error::ContextDisposed.into()),
+  };
 
-impl Plugin {
-  pub fn new() -> Self {
-    Self {}
+  let _style = style::get_style(_weak_ctx.clone()).await;
+
+  let _pm = _ctx.lock().await.perm_mgr.clone();
+
+  let _chat_id = _msg.chat.id;
+
+  if _cmd.values.flag("reset") {
+    _pm.reset_default(_chat_id)?;
+  } else if let Some(_perm) = _cmd.values.permission("perm") {
+    _pm.set_default(_chat_id, _perm)?;
   }
+
+  let _source = match _pm.chat_default(_chat_id)? {
+    Some(_) => "chat",
+    None => "global",
+  };
+
+  let text = format!(
+    "{} <b>Default Permission</b>\n\
+    {} <b>Chat:</b> <code>{}</code>\n\
+    {} <b>Permission:</b> <code>{:?}</code> ({})\n",
+    _style.bullet(),
+    _style.info(),
+    _chat_id,
+    _style.info(),
+    _pm.default_for(_chat_id)?,
+    _source,
+  );
+
+  _bot
+    .send_message(_chat_id, text)
+    .parse_mode(teloxide::types::ParseMode::Html)
+    .await?;
+
+  Ok(())
 }
 
-impl plugin::Plugin for Plugin {
-  fn name(&self) -> &str {
-    "access"
+async fn on_adminmap(
+  _bot: Bot,
+  _msg: Message,
+  _cmd: command::Command,
+  _weak_ctx: Weak<Mutex<context::Context>>,
+) -> anyhow::Result<()> {
+  let _ctx = match _weak_ctx.upgrade() {
+    Some(ctx) => ctx,
+    None => return Err(error::Error::ContextDisposed.into()),
+  };
+
+  let _style = style::get_style(_weak_ctx.clone()).await;
+
+  let (_settings, _admins) = {
+    let _ctx_guard = _ctx.lock().await;
+    (_ctx_guard.settings.clone(), _ctx_guard.admins.clone())
+  };
+
+  let _chat_id = _msg.chat.id;
+
+  match _cmd.values.str("state") {
+    Some("on") => _settings.set_admin_mapping(_chat_id, true)?,
+    Some("off") => _settings.set_admin_mapping(_chat_id, false)?,
+    _ => {}
   }
 
-  fn commands(&self) -> indexmap::IndexMap<String, command::CommandMetadata> {
-    let mut cmds = IndexMap::new();
+  let _enabled = _settings.get_admin_mapping(_chat_id)?;
+  _admins.invalidate(_chat_id);
 
-    let grant_cmd = CommandMetadata::new(
-      Permission::OWNER,
-      "Grant a specific permission to a user".to_string(),
-      ReplyRequirement::None,
-      vec![
-        ArgMetadata::new(
-          "user_id".to_string(),
-          "User ID to grant permission to".to_string(),
-          ArgRequirement::OnlyWithoutReply,
-        ),
-        ArgMetadata::new(
-          "perm".to_string(),
-          "Permission level to grant".to_string(),
-          ArgRequirement::Required,
-        ),
-      ],
-      Arc::new(|_bot, _msg, _cmd, _ctx| {
-        tokio::spawn(async move {
-          on_grant(_bot, _msg, _cmd, _ctx).await.unwrap_or(());
-        });
-      }),
-    );
+  let text = format!(
+    "{} <b>Administrator Mapping</b>\n\
+    {} <b>Chat:</b> <code>{}</code>\n\
+    {} <b>State:</b> {}\n",
+    _style.bullet(),
+    _style.info(),
+    _chat_id,
+    _style.info(),
+    if _enabled {
+      "on (creator is OWNER, administrators are ADMIN)"
+    } else {
+      "off"
+    },
+  );
 
-    let revoke_cmd = CommandMetadata::new(
-      Permission::OWNER,
-      "Revoke a specific permission from a user".to_string(),
-      ReplyRequirement::None,
-      vec![
-        ArgMetadata::new(
-          "user_id".to_string(),
-          "User ID to revoke permission from".to_string(),
-          ArgRequirement::OnlyWithoutReply,
-        ),
-        ArgMetadata::new(
-          "perm".to_string(),
-          "Permission level to revoke".to_string(),
-          ArgRequirement::Required,
-        ),
-      ],
-      Arc::new(|_bot, _msg, _cmd, _ctx| {
-        tokio::spawn(async move {
-          on_revoke(_bot, _msg, _cmd, _ctx).await.unwrap_or(());
-        });
-      }),
-    );
+  _bot
+    .send_message(_chat_id, text)
+    .parse_mode(teloxide::types::ParseMode::Html)
+    .await?;
 
-    let set_cmd = CommandMetadata::new(
-      Permission::OWNER,
-      "Set user permission level (replaces all existing)".to_string(),
-      ReplyRequirement::None,
-      vec![
-        ArgMetadata::new(
-          "user_id".to_string(),
-          "User ID to set permission for".to_string(),
-          ArgRequirement::OnlyWithoutReply,
-        ),
-        ArgMetadata::new(
-          "perm".to_string(),
-          "Permission level to set".to_string(),
-          ArgRequirement::Required,
-        ),
-      ],
-      Arc::new(|_bot, _msg, _cmd, _ctx| {
-        tokio::spawn(async move {
-          on_set(_bot, _msg, _cmd, _ctx).await.unwrap_or(());
-        });
-      }),
-    );
+  Ok(())
+}
 
-    let reset_cmd = CommandMetadata::new(
-      Permission::OWNER,
-      "Reset user permissions to default".to_string(),
-      ReplyRequirement::None,
-      vec![ArgMetadata::new(
-        "user_id".to_string(),
-        "User ID to reset permissions for".to_string(),
-        ArgRequirement::OnlyWithoutReply,
-      )],
-      Arc::new(|_bot, _msg, _cmd, _ctx| {
-        tokio::spawn(async move {
-          on_reset(_bot, _msg, _cmd, _ctx).await.unwrap_or(());
-        });
-      }),
-    );
+async fn on_cmdperm(
+  _bot: Bot,
+  _msg: Message,
+  _cmd: command::Command,
+  _weak_ctx: Weak<Mutex<context::Context>>,
+) -> anyhow::Result<()> {
+  let _ctx = match _weak_ctx.upgrade() {
+    Some(ctx) => ctx,
+    None => return Err(error::Error::ContextDisposed.into()),
+  };
 
-    let show_cmd = CommandMetadata::new(
-      Permission::OWNER,
-      "Display user permissions and access level".to_string(),
-      ReplyRequirement::None,
-      vec![ArgMetadata::new(
-        "user_id".to_string(),
-        "User ID to display permissions for".to_string(),
-        ArgRequirement::OnlyWithoutReply,
-      )],
-      Arc::new(|_bot, _msg, _cmd, _ctx| {
-        tokio::spawn(async move {
-          on_show(_bot, _msg, _cmd, _ctx).await.unwrap_or(());
-        });
-      }),
-    );
+  let _style = style::get_style(_weak_ctx.clone()).await;
+  let _scope = scope_of(&_msg, &_cmd);
 
-    cmds.insert("pmgrant".to_string(), grant_cmd);
-    cmds.insert("pmrevoke".to_string(), revoke_cmd);
-    cmds.insert("pmset".to_string(), set_cmd);
-    cmds.insert("pmreset".to_string(), reset_cmd);
-    cmds.insert("pmshow".to_string(), show_cmd);
+  let (_pm, _cfg, _shared_dp) = {
+    let _ctx_guard = _ctx.lock().await;
+    (_ctx_guard.perm_mgr.clone(), _ctx_guard.cfg.clone(), _ctx_guard.dp.clone())
+  };
 
-    cmds
-  }
+  let _requested = match _cmd.values.str("command") {
+    Some(requested) => requested,
+    None => {
+      let _overrides = _pm.overrides(_scope)?;
+      if _overrides.is_empty() {
+        return Err(CoreError::IsEmpty("override list".to_string()).into());
+      }
 
-  fn update_handlers(&self) -> Vec<handler::UpdateHandler> {
-    Vec::new()
+      let mut text = format!("{} <b>Command Overrides</b> ({}):\n", _style.bullet(), _scope);
+      for (name, ovr) in _overrides {
+        text.push_str(&format!(
+          "{} <code>{}</code> → <b>{}</b>\n",
+          _style.info(),
+          name,
+          ovr
+        ));
+      }
+
+      _bot
+        .send_message(_msg.chat.id, text)
+        .parse_mode(teloxide::types::ParseMode::Html)
+        .await?;
+
+      return Ok(());
+    }
+  };
+
+  let _case_insensitive = _cfg.read().await.is_case_insensitive();
+  let _dp = dispatcher::current(&_shared_dp).await;
+  let (_name, _declared) = match _dp.resolve(_requested, _case_insensitive) {
+    Some((name, info)) => (name.to_string(), info.perm),
+    None => return Err(CoreError::CommandNotFound(_requested.to_string()).into()),
+  };
+
+  if let Some(_level) = _cmd.values.str("level") {
+    if _name == "cmdperm" {
+      return Err(CoreError::PermissionDenied("cmdperm cannot be overridden".to_string()).into());
+    }
+
+    if _scope == Scope::Global {
+      require_global_owner(&_pm, &_msg)?;
+    }
+
+    match _level {
+      "reset" => _pm.reset_override(_scope, &_name)?,
+      "off" => _pm.set_override(_scope, &_name, CommandOverride::Disabled)?,
+      _level => {
+        let _perm = parsers::parse_permission(_level)
+          .await
+          .map_err(|_| CoreError::InvalidOption(format!("permission '{}'", _level)))?;
+        _pm.set_override(_scope, &_name, CommandOverride::Require(_perm))?;
+      }
+    }
   }
-}
 
-pub fn get_plugin() -> plugin::PluginBox {
+  let _effective = match _pm.command_override(_scope, &_name)? {
+    Some(ovr) => format!("{} (overridden)", ovr),
+    None => format!("{:?} (declared)", _declared),
+  };
+
+  let text = format!(
+    "{} <b>Command Permission</b>\n\
+    {} <b>Command:</b> <code>{}</code>\n\
+    {} <b>Scope:</b> {}\n\
+    {} <b>Required:</b> {}\n",
+    _style.bullet(),
+    _style.info(),
+    _name,
+    _style.info(),
+    _scope,
+    _style.info(),
+    _effective,
+  );
+
+  _bot
+    .send_message(_msg.chat.id, text)
+    .parse_mode(teloxide::types::ParseMode::Html)
+    .await?;
+
+  Ok(())
+}
+
+fn describe_plugin_state(state: Option<(Scope, bool)>) -> String {
+  match state {
+    Some((scope, true)) => format!("enabled ({})", scope),
+    Some((scope, false)) => format!("disabled ({})", scope),
+    None => "enabled (default)".to_string(),
+  }
+}
+
+/// Whether a `/plugin` action needs a global owner: any change to the global
+/// state, and enabling a plugin in a chat while it is disabled globally, as
+/// the chat's state would win over the global one.
+fn plugin_change_needs_global_owner(
+  settings: &SettingsManager,
+  scope: Scope,
+  action: &str,
+  plugin: &str,
+) -> anyhow::Result<bool> {
+  if scope == Scope::Global {
+    return Ok(true);
+  }
+
+  Ok(action == "enable" && settings.get_plugin_state(Scope::Global, plugin)? == Some(false))
+}
+
+async fn on_plugins(
+  _bot: Bot,
+  _msg: Message,
+  _cmd: command::Command,
+  _weak_ctx: Weak<Mutex<context::Context>>,
+) -> anyhow::Result<()> {
+  let _ctx = match _weak_ctx.upgrade() {
+    Some(ctx) => ctx,
+    None => return Err(error::Error::ContextDisposed.into()),
+  };
+
+  let _style = style::get_style(_weak_ctx.clone()).await;
+  let _scope = scope_of(&_msg, &_cmd);
+
+  let (_shared_dp, _settings) = {
+    let _ctx_guard = _ctx.lock().await;
+    (_ctx_guard.dp.clone(), _ctx_guard.settings.clone())
+  };
+  let _dp = dispatcher::current(&_shared_dp).await;
+
+  let mut text = format!("{} <b>Plugins</b> ({}):\n", _style.bullet(), _scope);
+  for name in _dp.plugins.keys() {
+    let _commands = _dp
+      .command_handlers
+      .values()
+      .filter(|meta| meta.plugin == *name)
+      .count();
+
+    text.push_str(&format!(
+      "{} <code>{}</code> → <b>{}</b>, {} commands\n",
+      _style.info(),
+      name,
+      describe_plugin_state(_settings.resolve_plugin_state(_scope, name)?),
+      _commands
+    ));
+  }
+
+  _bot
+    .send_message(_msg.chat.id, text)
+    .parse_mode(teloxide::types::ParseMode::Html)
+    .await?;
+
+  Ok(())
+}
+
+async fn on_plugin(
+  _bot: Bot,
+  _msg: Message,
+  _cmd: command::Command,
+  _weak_ctx: Weak<Mutex<context::Context>>,
+) -> anyhow::Result<()> {
+  let _ctx = match _weak_ctx.upgrade() {
+    Some(ctx) => ctx,
+    None => return Err(error::Error::ContextDisposed.into()),
+  };
+
+  let _style = style::get_style(_weak_ctx.clone()).await;
+  let _scope = scope_of(&_msg, &_cmd);
+
+  let (_pm, _shared_dp, _settings, _audit) = {
+    let _ctx_guard = _ctx.lock().await;
+    (
+      _ctx_guard.perm_mgr.clone(),
+      _ctx_guard.dp.clone(),
+      _ctx_guard.settings.clone(),
+      _ctx_guard.audit.clone(),
+    )
+  };
+
+  let _action = match _cmd.values.str("action") {
+    Some(action) => action,
+    None => return Err(CoreError::OptionNotSpecified("action".to_string()).into()),
+  };
+  let _requested = match _cmd.values.str("plugin") {
+    Some(plugin) => plugin,
+    None => return Err(CoreError::OptionNotSpecified("plugin".to_string()).into()),
+  };
+
+  let _dp = dispatcher::current(&_shared_dp).await;
+  let _name = match _dp
+    .plugins
+    .keys()
+    .find(|name| name.eq_ignore_ascii_case(_requested))
+  {
+    Some(name) => name.clone(),
+    None => return Err(CoreError::NotFound(format!("plugin {}", _requested)).into()),
+  };
+
+  if _action == "disable" && _name == PLUGIN_NAME {
+    return Err(
+      CoreError::PermissionDenied(format!("{} manages plugins and cannot be disabled", _name))
+        .into(),
+    );
+  }
+
+  if plugin_change_needs_global_owner(&_settings, _scope, _action, &_name)? {
+    require_global_owner(&_pm, &_msg)?;
+  }
+
+  let (_before, _after) = {
+    let _before = _settings.resolve_plugin_state(_scope, &_name)?;
+
+    match _action {
+      "enable" => _settings.set_plugin_state(_scope, &_name, true)?,
+      "disable" => _settings.set_plugin_state(_scope, &_name, false)?,
+      "reset" => _settings.reset_plugin_state(_scope, &_name)?,
+      _action => return Err(CoreError::UnknownOption(format!("action '{}'", _action)).into()),
+    }
+
+    (_before, _settings.resolve_plugin_state(_scope, &_name)?)
+  };
+
+  record_audit(
+    &_audit,
+    AuditEntry {
+      chat_id: Some(_msg.chat.id),
+      actor: _msg.from.as_ref().map(|user| user.id),
+      action: format!("plugin.{}", _action),
+      command: Some("plugin".to_string()),
+      before: Some(describe_plugin_state(_before)),
+      after: Some(describe_plugin_state(_after)),
+      detail: Some(format!("{} in {}", _name, _scope)),
+      ..Default::default()
+    },
+  )
+  .await;
+
+  let text = format!(
+    "{} <b>Plugin Update</b>\n\
+    {} <b>Plugin:</b> <code>{}</code>\n\
+    {} <b>Scope:</b> {}\n\
+    {} <b>State:</b> {}\n",
+    _style.info(),
+    _style.info(),
+    _name,
+    _style.info(),
+    _scope,
+    _style.info(),
+    describe_plugin_state(_after),
+  );
+
+  _bot
+    .send_message(_msg.chat.id, text)
+    .parse_mode(teloxide::types::ParseMode::Html)
+    .await?;
+
+  Ok(())
+}
+
+async fn on_role(
+  _bot: Bot,
+  _msg: Message,
+  _cmd: command::Command,
+  _weak_ctx: Weak<Mutex<context::Context>>,
+) -> anyhow::Result<()> {
+  let _ctx = match _weak_ctx.upgrade() {
+    Some(ctx) => ctx,
+    None => return Err(error::Error::ContextDisposed.into()),
+  };
+
+  let _style = style::get_style(_weak_ctx.clone()).await;
+
+  let (_pm, _roles) = {
+    let _ctx_guard = _ctx.lock().await;
+    (_ctx_guard.perm_mgr.clone(), _ctx_guard.roles.clone())
+  };
+
+  let _action = _cmd.values.str("action").unwrap_or("list");
+  let _name = _cmd.values.str("role");
+  let _capability = _cmd.values.str("capability");
+
+  if _action != "list" && _action != "show" {
+    require_global_owner(&_pm, &_msg)?;
+  }
+
+  let _role_name = || match _name {
+    Some(name) => Ok(name),
+    None => Err(CoreError::OptionNotSpecified("role".to_string())),
+  };
+  let _capability_name = || match _capability {
+    Some(capability) => Ok(capability),
+    None => Err(CoreError::OptionNotSpecified("capability".to_string())),
+  };
+
+  match _action {
+    "create" => _roles.create(_role_name()?)?,
+    "delete" => _roles.delete(_role_name()?)?,
+    "allow" => _roles.allow(_role_name()?, _capability_name()?)?,
+    "deny" => _roles.deny(_role_name()?, _capability_name()?)?,
+    _ => {}
+  }
+
+  let _listed = match (_action, _name) {
+    ("list", _) => _roles.roles()?,
+    ("delete", _) => _roles.roles()?,
+    (_, Some(name)) => vec![_roles.role(name)?],
+    (_, None) => return Err(CoreError::OptionNotSpecified("role".to_string()).into()),
+  };
+
+  let mut text = format!("{} <b>Roles:</b>\n", _style.bullet());
+  for role in _listed {
+    let capabilities = if role.capabilities.is_empty() {
+      "none".to_string()
+    } else {
+      role
+        .capabilities
+        .iter()
+        .map(|cap| format!("<code>{}</code>", cap))
+        .collect::<Vec<_>>()
+        .join(", ")
+    };
+
+    text.push_str(&format!(
+      "{} <b>{}</b>{}\n  └─ Capabilities: {}\n",
+      _style.info(),
+      role.name,
+      if role.builtin { " (built-in)" } else { "" },
+      capabilities
+    ));
+  }
+
+  _bot
+    .send_message(_msg.chat.id, text)
+    .parse_mode(teloxide::types::ParseMode::Html)
+    .await?;
+
+  Ok(())
+}
+
+async fn handle_role_assignment(
+  _bot: Bot,
+  _msg: Message,
+  _cmd: command::Command,
+  _weak_ctx: Weak<Mutex<context::Context>>,
+  _assign: bool,
+) -> anyhow::Result<()> {
+  let _ctx = match _weak_ctx.upgrade() {
+    Some(ctx) => ctx,
+    None => return Err(error::Error::ContextDisposed.into()),
+  };
+
+  let _style = style::get_style(_weak_ctx.clone()).await;
+  let _scope = scope_of(&_msg, &_cmd);
+
+  let _user_id = match _cmd.values.user_id("user_id") {
+    Some(id) => id,
+    None => return Err(CoreError::OptionNotSpecified("user_id".to_string()).into()),
+  };
+
+  let _role = match _cmd.values.str("role") {
+    Some(role) => role,
+    None => return Err(CoreError::OptionNotSpecified("role".to_string()).into()),
+  };
+
+  let (_pm, _roles, _audit) = {
+    let _ctx_guard = _ctx.lock().await;
+    (_ctx_guard.perm_mgr.clone(), _ctx_guard.roles.clone(), _ctx_guard.audit.clone())
+  };
+
+  if _scope == Scope::Global {
+    require_global_owner(&_pm, &_msg)?;
+  }
+  let _before = _roles.roles_of(_scope, _user_id)?.join(", ");
+
+  if _assign {
+    _roles.assign(_scope, _user_id, _role)?;
+  } else {
+    _roles.unassign(_scope, _user_id, _role)?;
+  }
+
+  let _after = _roles.roles_of(_scope, _user_id)?.join(", ");
+
+  record_audit(
+    &_audit,
+    AuditEntry {
+      chat_id: Some(_msg.chat.id),
+      actor: _msg.from.as_ref().map(|user| user.id),
+      action: if _assign { "role.assign" } else { "role.unassign" }.to_string(),
+      target: Some(_user_id),
+      command: Some(_cmd.name.clone()),
+      before: Some(_before),
+      after: Some(_after),
+      detail: Some(_scope.to_string()),
+      ..Default::default()
+    },
+  )
+  .await;
+
+  let text = format!(
+    "{} <b>Role Update</b>\n\
+    {} <b>User:</b> <code>{}</code>\n\
+    {} <b>Scope:</b> {}\n\
+    {} <b>Role:</b> <code>{}</code>\n\
+    {} <b>Action:</b> {}\n",
+    _style.info(),
+    _style.info(),
+    _user_id,
+    _style.info(),
+    _scope,
+    _style.info(),
+    _role,
+    _style.info(),
+    if _assign { "assigned" } else { "unassigned" },
+  );
+
+  _bot
+    .send_message(_msg.chat.id, text)
+    .parse_mode(teloxide::types::ParseMode::Html)
+    .await?;
+
+  Ok(())
+}
+
+async fn on_role_add(
+  bot: Bot,
+  msg: Message,
+  cmd: command::Command,
+  ctx: Weak<Mutex<context::Context>>,
+) -> anyhow::Result<()> {
+  handle_role_assignment(bot, msg, cmd, ctx, true).await
+}
+
+async fn on_role_del(
+  bot: Bot,
+  msg: Message,
+  cmd: command::Command,
+  ctx: Weak<Mutex<context::Context>>,
+) -> anyhow::Result<()> {
+  handle_role_assignment(bot, msg, cmd, ctx, false).await
+}
+
+fn role_assignment_args(verb: &str) -> Vec<ArgMetadata> {
+  vec![
+    ArgMetadata::new(
+      "user_id".to_string(),
+      format!("User ID to {} the role", verb),
+      ArgKind::UserId,
+      ArgRequirement::OnlyWithoutReply,
+    ),
+    ArgMetadata::new(
+      "role".to_string(),
+      "Name of the role".to_string(),
+      ArgKind::String,
+      ArgRequirement::Required,
+    ),
+  ]
+}
+
+/// The user or chat a ban command is about: the `target` argument, read as
+/// a chat id with `--chat`, or else the replied user or the current chat.
+fn block_target(
+  msg: &Message,
+  cmd: &command::Command,
+) -> anyhow::Result<BlockTarget> {
+  let is_chat = cmd.values.flag("chat");
+
+  match cmd.values.str("target") {
+    Some(raw) if is_chat => raw
+      .parse::<i64>()
+      .map(|id| BlockTarget::Chat(ChatId(id)))
+      .map_err(|_| CoreError::InvalidOption(format!("chat id '{}'", raw)).into()),
+    Some(raw) => raw
+      .parse::<u64>()
+      .map(|id| BlockTarget::User(UserId(id)))
+      .map_err(|_| CoreError::InvalidOption(format!("user id '{}'", raw)).into()),
+    None if is_chat => Ok(BlockTarget::Chat(msg.chat.id)),
+    None => match msg.reply_to_message().and_then(|reply| reply.from.as_ref()) {
+      Some(user) => Ok(BlockTarget::User(user.id)),
+      None => Err(CoreError::OptionNotSpecified("target".to_string()).into()),
+    },
+  }
+}
+
+async fn on_ban(
+  _bot: Bot,
+  _msg: Message,
+  _cmd: command::Command,
+  _weak_ctx: Weak<Mutex<context::Context>>,
+) -> anyhow::Result<()> {
+  let _ctx = match _weak_ctx.upgrade() {
+    Some(ctx) => ctx,
+    None => return Err(error::Error::ContextDisposed.into()),
+  };
+
+  let _style = style::get_style(_weak_ctx.clone()).await;
+  let _target = block_target(&_msg, &_cmd)?;
+
+  let (_pm, _blocklist) = {
+    let _ctx_guard = _ctx.lock().await;
+    (_ctx_guard.perm_mgr.clone(), _ctx_guard.blocklist.clone())
+  };
+
+  require_global_owner(&_pm, &_msg)?;
+
+  if let BlockTarget::User(_uid) = _target
+    && _pm.can(Scope::Global, _uid, Permission::OWNER)?
+  {
+    return Err(CoreError::PermissionDenied("owners cannot be banned".to_string()).into());
+  }
+
+  let _entry = _blocklist.block(
+    _target,
+    _cmd.values.str("reason"),
+    _cmd.values.duration("for"),
+  )?;
+
+  let text = format!(
+    "{} <b>Banned</b>\n\
+    {} <b>Target:</b> <code>{}</code>\n\
+    {} <b>Reason:</b> {}\n\
+    {} <b>Until:</b> {}\n",
+    _style.info(),
+    _style.info(),
+    _entry.target,
+    _style.info(),
+    teloxide::utils::html::escape(_entry.reason.as_deref().unwrap_or("none")),
+    _style.info(),
+    _entry
+      .expires_at
+      .map(|at| at.format("%Y-%m-%d %H:%M:%S UTC").to_string())
+      .unwrap_or_else(|| "forever".to_string()),
+  );
+
+  _bot
+    .send_message(_msg.chat.id, text)
+    .parse_mode(teloxide::types::ParseMode::Html)
+    .await?;
+
+  Ok(())
+}
+
+async fn on_unban(
+  _bot: Bot,
+  _msg: Message,
+  _cmd: command::Command,
+  _weak_ctx: Weak<Mutex<context::Context>>,
+) -> anyhow::Result<()> {
+  let _ctx = match _weak_ctx.upgrade() {
+    Some(ctx) => ctx,
+    None => return Err(error::Error::ContextDisposed.into()),
+  };
+
+  let _style = style::get_style(_weak_ctx.clone()).await;
+  let _target = block_target(&_msg, &_cmd)?;
+
+  let (_pm, _blocklist) = {
+    let _ctx_guard = _ctx.lock().await;
+    (_ctx_guard.perm_mgr.clone(), _ctx_guard.blocklist.clone())
+  };
+
+  require_global_owner(&_pm, &_msg)?;
+
+  if !_blocklist.unblock(_target)? {
+    return Err(CoreError::NotFound(format!("ban of {}", _target)).into());
+  }
+
+  let text = format!(
+    "{} <b>Unbanned</b>\n{} <b>Target:</b> <code>{}</code>\n",
+    _style.info(),
+    _style.info(),
+    _target,
+  );
+
+  _bot
+    .send_message(_msg.chat.id, text)
+    .parse_mode(teloxide::types::ParseMode::Html)
+    .await?;
+
+  Ok(())
+}
+
+async fn on_banlist(
+  _bot: Bot,
+  _msg: Message,
+  _cmd: command::Command,
+  _weak_ctx: Weak<Mutex<context::Context>>,
+) -> anyhow::Result<()> {
+  let _ctx = match _weak_ctx.upgrade() {
+    Some(ctx) => ctx,
+    None => return Err(error::Error::ContextDisposed.into()),
+  };
+
+  let _style = style::get_style(_weak_ctx.clone()).await;
+
+  let _blocklist = _ctx.lock().await.blocklist.clone();
+  let _entries = _blocklist.entries()?;
+
+  if _entries.is_empty() {
+    return Err(CoreError::IsEmpty("ban list".to_string()).into());
+  }
+
+  let mut text = format!("{} <b>Ban List:</b>\n\n", _style.bullet());
+  for entry in _entries {
+    text.push_str(&format!(
+      "{} <code>{}</code>\n  ├─ Reason: {}\n  └─ Until: {}\n",
+      _style.info(),
+      entry.target,
+      teloxide::utils::html::escape(entry.reason.as_deref().unwrap_or("none")),
+      entry
+        .expires_at
+        .map(|at| at.format("%Y-%m-%d %H:%M:%S UTC").to_string())
+        .unwrap_or_else(|| "forever".to_string()),
+    ));
+  }
+
+  _bot
+    .send_message(_msg.chat.id, text)
+    .parse_mode(teloxide::types::ParseMode::Html)
+    .await?;
+
+  Ok(())
+}
+
+fn ban_target_arg() -> ArgMetadata {
+  ArgMetadata::new(
+    "target".to_string(),
+    "User ID, or chat ID with --chat".to_string(),
+    ArgKind::String,
+    ArgRequirement::OnlyWithoutReply,
+  )
+}
+
+fn ban_chat_opt() -> OptMetadata {
+  OptMetadata::new(
+    "chat".to_string(),
+    Some('c'),
+    "Target a chat instead of a user; the current chat when replying".to_string(),
+    None,
+  )
+}
+
+/// Largest permission document `/pmimport` accepts.
+const MAX_IMPORT_SIZE: u32 = 1024 * 1024;
+
+/// Most changes listed in an import preview.
+const MAX_PREVIEW_CHANGES: usize = 30;
+
+async fn on_export(
+  _bot: Bot,
+  _msg: Message,
+  _cmd: command::Command,
+  _weak_ctx: Weak<Mutex<context::Context>>,
+) -> anyhow::Result<()> {
+  let _ctx = match _weak_ctx.upgrade() {
+    Some(ctx) => ctx,
+    None => return Err(error::Error::ContextDisposed.into()),
+  };
+
+  let _style = style::get_style(_weak_ctx.clone()).await;
+
+  let _pm = _ctx.lock().await.perm_mgr.clone();
+  require_global_owner(&_pm, &_msg)?;
+  let _snapshot = _pm.full_snapshot()?;
+
+  let _json = serde_json::to_vec_pretty(&_snapshot)?;
+
+  _bot
+    .send_document(
+      _msg.chat.id,
+      InputFile::memory(_json).file_name("permissions.json"),
+    )
+    .caption(format!(
+      "{} <b>{} permission entries exported</b>",
+      _style.bullet(),
+      _snapshot.len()
+    ))
+    .parse_mode(teloxide::types::ParseMode::Html)
+    .await?;
+
+  Ok(())
+}
+
+fn describe_change(change: &SnapshotChange) -> String {
+  let kind = match (change.before, change.after) {
+    (None, Some(_)) => "add",
+    (Some(_), None) => "remove",
+    _ => "change",
+  };
+
+  format!(
+    "{} <code>{}</code> ({}): {} → {}",
+    kind,
+    change.user_id,
+    change.scope,
+    describe_perm(change.before),
+    describe_perm(change.after)
+  )
+}
+
+async fn on_import(
+  _bot: Bot,
+  _msg: Message,
+  _cmd: command::Command,
+  _weak_ctx: Weak<Mutex<context::Context>>,
+) -> anyhow::Result<()> {
+  let _ctx = match _weak_ctx.upgrade() {
+    Some(ctx) => ctx,
+    None => return Err(error::Error::ContextDisposed.into()),
+  };
+
+  let _style = style::get_style(_weak_ctx.clone()).await;
+
+  let (_pm, _audit) = {
+    let _ctx_guard = _ctx.lock().await;
+    (_ctx_guard.perm_mgr.clone(), _ctx_guard.audit.clone())
+  };
+
+  require_global_owner(&_pm, &_msg)?;
+
+  let _document = match _msg.reply_to_message().and_then(|reply| reply.document()) {
+    Some(document) => document,
+    None => return Err(CoreError::OptionNotSpecified("reply to a document".to_string()).into()),
+  };
+
+  if _document.file.size > MAX_IMPORT_SIZE {
+    return Err(
+      CoreError::InvalidOption(format!("document larger than {} bytes", MAX_IMPORT_SIZE)).into(),
+    );
+  }
+
+  let _file = _bot.get_file(_document.file.id.clone()).await?;
+  let mut _raw: Vec<u8> = Vec::new();
+  _bot.download_file(&_file.path, &mut _raw).await?;
+
+  let _snapshot: PermissionSnapshot = serde_json::from_slice(&_raw)
+    .map_err(|err| CoreError::InvalidOption(format!("permission document: {}", err)))?;
+
+  let _mode = if _cmd.values.flag("replace") {
+    ImportMode::Replace
+  } else {
+    ImportMode::Merge
+  };
+  let _apply = _cmd.values.flag("apply");
+
+  if _snapshot.chats.contains_key(&Scope::Global.key()) {
+    return Err(
+      CoreError::InvalidOption("permission document: chat 0 is reserved for global".to_string())
+        .into(),
+    );
+  }
+
+  // Entries of the caller are applied as they are, and a replace drops a
+  // missing one, so either could lock them out of the bot.
+  if let Some(user) = &_msg.from {
+    let _kept_level = match (_snapshot.global.get(&user.id), _mode) {
+      (Some(perm), _) => perm.level(),
+      (None, ImportMode::Replace) => 0,
+      (None, ImportMode::Merge) => Permission::OWNER.level(),
+    };
+
+    if _kept_level < Permission::OWNER.level() {
+      return Err(
+        CoreError::PermissionDenied("the import would take away your global OWNER".to_string())
+          .into(),
+      );
+    }
+  }
+
+  let _changes = if _apply {
+    _pm.import_snapshot(&_snapshot, _mode)?
+  } else {
+    _pm.diff_snapshot(&_snapshot, _mode)?
+  };
+
+  if _apply {
+    record_audit(
+      &_audit,
+      AuditEntry {
+        chat_id: Some(_msg.chat.id),
+        actor: _msg.from.as_ref().map(|user| user.id),
+        action: "permission.import".to_string(),
+        command: Some(_cmd.name.clone()),
+        detail: Some(format!(
+          "{:?}: {} entries, {} changes",
+          _mode,
+          _snapshot.len(),
+          _changes.len()
+        )),
+        ..Default::default()
+      },
+    )
+    .await;
+  }
+
+  let mut text = format!(
+    "{} <b>{}</b> ({:?}, {} entries, {} changes)\n",
+    _style.bullet(),
+    if _apply { "Import applied" } else { "Import preview" },
+    _mode,
+    _snapshot.len(),
+    _changes.len()
+  );
+
+  for change in _changes.iter().take(MAX_PREVIEW_CHANGES) {
+    text.push_str(&format!("{} {}\n", _style.info(), describe_change(change)));
+  }
+
+  if _changes.len() > MAX_PREVIEW_CHANGES {
+    text.push_str(&format!(
+      "{} ... and {} more\n",
+      _style.info(),
+      _changes.len() - MAX_PREVIEW_CHANGES
+    ));
+  }
+
+  if !_apply && !_changes.is_empty() {
+    text.push_str(&format!(
+      "\n{} Run again with <code>--apply</code> to import.",
+      _style.arrow()
+    ));
+  }
+
+  _bot
+    .send_message(_msg.chat.id, text)
+    .parse_mode(teloxide::types::ParseMode::Html)
+    .await?;
+
+  Ok(())
+}
+
+const AUDIT_PAGE_SIZE: usize = 10;
+
+/// Characters of an entry's detail shown per entry, keeping a full page below
+/// Telegram's 4096 character message limit.
+const AUDIT_DETAIL_LIMIT: usize = 200;
+
+async fn on_audit(
+  _bot: Bot,
+  _msg: Message,
+  _cmd: command::Command,
+  _weak_ctx: Weak<Mutex<context::Context>>,
+) -> anyhow::Result<()> {
+  let _ctx = match _weak_ctx.upgrade() {
+    Some(ctx) => ctx,
+    None => return Err(error::Error::ContextDisposed.into()),
+  };
+
+  let _style = style::get_style(_weak_ctx.clone()).await;
+
+  let _now = chrono::Utc::now();
+  let _ago = |duration: std::time::Duration| {
+    chrono::Duration::from_std(duration)
+      .ok()
+      .and_then(|duration| _now.checked_sub_signed(duration))
+  };
+
+  let _filter = AuditFilter {
+    user: _cmd.values.user_id("user"),
+    command: _cmd.values.str("command").map(|c| c.to_string()),
+    since: _cmd.values.duration("since").and_then(_ago),
+    until: _cmd.values.duration("until").and_then(_ago),
+  };
+
+  let _audit = _ctx.lock().await.audit.clone();
+
+  let (_total, _entries, _page, _pages) = {
+    let _total = _audit.count(&_filter)?;
+    let _pages = _total.div_ceil(AUDIT_PAGE_SIZE).max(1);
+    let _page = (_cmd.values.integer("page").unwrap_or(1).max(1) as usize).min(_pages);
+
+    let _entries = _audit.query(&_filter, AUDIT_PAGE_SIZE, (_page - 1) * AUDIT_PAGE_SIZE)?;
+    (_total, _entries, _page, _pages)
+  };
+
+  if _entries.is_empty() {
+    return Err(CoreError::IsEmpty("audit log".to_string()).into());
+  }
+
+  let mut text = format!(
+    "{} <b>Audit Log</b> (page {} of {}, {} entries):\n\n",
+    _style.bullet(),
+    _page,
+    _pages,
+    _total
+  );
+
+  for entry in _entries {
+    let actor = entry
+      .actor
+      .map(|id| id.to_string())
+      .unwrap_or_else(|| "unknown".to_string());
+
+    text.push_str(&format!(
+      "{} <code>#{}</code> {} <b>{}</b> by <code>{}</code>",
+      _style.info(),
+      entry.id,
+      entry.at.format("%Y-%m-%d %H:%M:%S"),
+      entry.action,
+      actor
+    ));
+
+    if let Some(command) = &entry.command {
+      text.push_str(&format!(" via <code>{}</code>", command));
+    }
+
+    if let Some(chat_id) = entry.chat_id {
+      text.push_str(&format!(" in <code>{}</code>", chat_id));
+    }
+
+    text.push('\n');
+
+    if let Some(target) = entry.target {
+      text.push_str(&format!(
+        "  ├─ User: <code>{}</code>: {} → {}\n",
+        target,
+        teloxide::utils::html::escape(entry.before.as_deref().unwrap_or("?")),
+        teloxide::utils::html::escape(entry.after.as_deref().unwrap_or("?")),
+      ));
+    }
+
+    if let Some(detail) = &entry.detail {
+      text.push_str(&format!(
+        "  └─ {}\n",
+        teloxide::utils::html::escape(&formatter::truncate(detail, AUDIT_DETAIL_LIMIT))
+      ));
+    }
+  }
+
+  _bot
+    .send_message(_msg.chat.id, text)
+    .parse_mode(teloxide::types::ParseMode::Html)
+    .await?;
+
+  Ok(())
+}
+
+const PLUGIN_NAME: &str = "access";
+
+#[derive(Default)]
+pub struct Plugin {}
+
+impl Plugin {
+  pub fn new() -> Self {
+    Self {}
+  }
+}
+
+impl plugin::Plugin for Plugin {
+  fn name(&self) -> &str {
+    PLUGIN_NAME
+  }
+
+  fn commands(&self) -> indexmap::IndexMap<String, command::CommandMetadata> {
+    let mut cmds = IndexMap::new();
+
+    let grant_cmd = CommandMetadata::new(
+      Permission::OWNER,
+      "Grant a specific permission to a user".to_string(),
+      ReplyRequirement::Optional,
+      vec![
+        ArgMetadata::new(
+          "user_id".to_string(),
+          "User ID to grant permission to".to_string(),
+          ArgKind::UserId,
+          ArgRequirement::OnlyWithoutReply,
+        ),
+        ArgMetadata::new(
+          "perm".to_string(),
+          "Permission level to grant".to_string(),
+          ArgKind::Permission,
+          ArgRequirement::Required,
+        ),
+        ArgMetadata::new(
+          "duration".to_string(),
+          "Revoke the grant automatically after this long, e.g. 2h".to_string(),
+          ArgKind::Duration,
+          ArgRequirement::Optional,
+        ),
+      ],
+      Arc::new(|_bot, _msg, _cmd, _ctx| Box::pin(on_grant(_bot, _msg, _cmd, _ctx))),
+    )
+    .with_capability("access.grant")
+    .with_strict_args()
+    .with_opts(vec![global_opt()]);
+
+    let revoke_cmd = CommandMetadata::new(
+      Permission::OWNER,
+      "Revoke a specific permission from a user".to_string(),
+      ReplyRequirement::Optional,
+      vec![
+        ArgMetadata::new(
+          "user_id".to_string(),
+          "User ID to revoke permission from".to_string(),
+          ArgKind::UserId,
+          ArgRequirement::OnlyWithoutReply,
+        ),
+        ArgMetadata::new(
+          "perm".to_string(),
+          "Permission level to revoke".to_string(),
+          ArgKind::Permission,
+          ArgRequirement::Required,
+        ),
+      ],
+      Arc::new(|_bot, _msg, _cmd, _ctx| Box::pin(on_revoke(_bot, _msg, _cmd, _ctx))),
+    )
+    .with_capability("access.revoke")
+    .with_strict_args()
+    .with_opts(vec![global_opt()]);
+
+    let set_cmd = CommandMetadata::new(
+      Permission::OWNER,
+      "Set user permission level (replaces all existing)".to_string(),
+      ReplyRequirement::Optional,
+      vec![
+        ArgMetadata::new(
+          "user_id".to_string(),
+          "User ID to set permission for".to_string(),
+          ArgKind::UserId,
+          ArgRequirement::OnlyWithoutReply,
+        ),
+        ArgMetadata::new(
+          "perm".to_string(),
+          "Permission level to set".to_string(),
+          ArgKind::Permission,
+          ArgRequirement::Required,
+        ),
+      ],
+      Arc::new(|_bot, _msg, _cmd, _ctx| Box::pin(on_set(_bot, _msg, _cmd, _ctx))),
+    )
+    .with_capability("access.set")
+    .with_strict_args()
+    .with_opts(vec![global_opt()]);
+
+    let reset_cmd = CommandMetadata::new(
+      Permission::OWNER,
+      "Reset user permissions to default".to_string(),
+      ReplyRequirement::Optional,
+      vec![ArgMetadata::new(
+        "user_id".to_string(),
+        "User ID to reset permissions for".to_string(),
+        ArgKind::UserId,
+        ArgRequirement::OnlyWithoutReply,
+      )],
+      Arc::new(|_bot, _msg, _cmd, _ctx| Box::pin(on_reset(_bot, _msg, _cmd, _ctx))),
+    )
+    .with_capability("access.reset")
+    .with_strict_args()
+    .with_opts(vec![global_opt()]);
+
+    let show_cmd = CommandMetadata::new(
+      Permission::OWNER,
+      "Display user permissions and access level".to_string(),
+      ReplyRequirement::Optional,
+      vec![ArgMetadata::new(
+        "user_id".to_string(),
+        "User ID to display permissions for".to_string(),
+        ArgKind::UserId,
+        ArgRequirement::Optional,
+      )],
+      Arc::new(|_bot, _msg, _cmd, _ctx| Box::pin(on_show(_bot, _msg, _cmd, _ctx))),
+    )
+    .with_aliases(&["perms"])
+    .with_opts(vec![
+      OptMetadata::new(
+        "level".to_string(),
+        Some('l'),
+        "Only list users at this permission level".to_string(),
+        Some(ArgKind::Permission),
+      ),
+      global_opt(),
+    ]);
+
+    let default_cmd = CommandMetadata::new(
+      Permission::OWNER,
+      "Show or change the permission of users without an entry in this chat".to_string(),
+      ReplyRequirement::None,
+      vec![ArgMetadata::new(
+        "perm".to_string(),
+        "Default permission level for this chat".to_string(),
+        ArgKind::Permission,
+        ArgRequirement::Optional,
+      )],
+      Arc::new(|_bot, _msg, _cmd, _ctx| Box::pin(on_default(_bot, _msg, _cmd, _ctx))),
+    )
+    .with_strict_args()
+    .with_opts(vec![OptMetadata::new(
+      "reset".to_string(),
+      Some('r'),
+      "Fall back to the global default".to_string(),
+      None,
+    )]);
+
+    let adminmap_cmd = CommandMetadata::new(
+      Permission::OWNER,
+      "Show or toggle treating Telegram chat administrators as bot admins in this chat".to_string(),
+      ReplyRequirement::None,
+      vec![ArgMetadata::new(
+        "state".to_string(),
+        "Whether administrators are mapped".to_string(),
+        ArgKind::Choice(vec!["on".to_string(), "off".to_string()]),
+        ArgRequirement::Optional,
+      )],
+      Arc::new(|_bot, _msg, _cmd, _ctx| Box::pin(on_adminmap(_bot, _msg, _cmd, _ctx))),
+    );
+
+    let cmdperm_cmd = CommandMetadata::new(
+      Permission::OWNER,
+      "Show or override the permission a command requires in this chat".to_string(),
+      ReplyRequirement::None,
+      vec![
+        ArgMetadata::new(
+          "command".to_string(),
+          "Command to inspect; lists all overrides when omitted".to_string(),
+          ArgKind::String,
+          ArgRequirement::Optional,
+        ),
+        ArgMetadata::new(
+          "level".to_string(),
+          "Required permission, `off` to disable or `reset` to restore".to_string(),
+          ArgKind::String,
+          ArgRequirement::Optional,
+        ),
+      ],
+      Arc::new(|_bot, _msg, _cmd, _ctx| Box::pin(on_cmdperm(_bot, _msg, _cmd, _ctx))),
+    )
+    .with_strict_args()
+    .with_opts(vec![global_opt()]);
+
+    let plugins_cmd = CommandMetadata::new(
+      Permission::ADMIN,
+      "List loaded plugins and whether they are enabled here".to_string(),
+      ReplyRequirement::None,
+      vec![],
+      Arc::new(|_bot, _msg, _cmd, _ctx| Box::pin(on_plugins(_bot, _msg, _cmd, _ctx))),
+    )
+    .with_opts(vec![global_opt()]);
+
+    let plugin_cmd = CommandMetadata::new(
+      Permission::OWNER,
+      "Enable or disable a plugin in this chat".to_string(),
+      ReplyRequirement::None,
+      vec![
+        ArgMetadata::new(
+          "action".to_string(),
+          "`enable`, `disable` or `reset` to follow the global state".to_string(),
+          ArgKind::Choice(
+            ["enable", "disable", "reset"]
+              .iter()
+              .map(|action| action.to_string())
+              .collect(),
+          ),
+          ArgRequirement::Required,
+        ),
+        ArgMetadata::new(
+          "plugin".to_string(),
+          "Name of the plugin".to_string(),
+          ArgKind::String,
+          ArgRequirement::Required,
+        ),
+      ],
+      Arc::new(|_bot, _msg, _cmd, _ctx| Box::pin(on_plugin(_bot, _msg, _cmd, _ctx))),
+    )
+    .with_strict_args()
+    .with_opts(vec![global_opt()]);
+
+    let role_cmd = CommandMetadata::new(
+      Permission::OWNER,
+      "List, inspect or edit roles and their capabilities".to_string(),
+      ReplyRequirement::None,
+      vec![
+        ArgMetadata::new(
+          "action".to_string(),
+          "What to do with the role".to_string(),
+          ArgKind::Choice(
+            ["list", "show", "create", "delete", "allow", "deny"]
+              .iter()
+              .map(|action| action.to_string())
+              .collect(),
+          ),
+          ArgRequirement::Optional,
+        ),
+        ArgMetadata::new(
+          "role".to_string(),
+          "Name of the role".to_string(),
+          ArgKind::String,
+          ArgRequirement::Optional,
+        ),
+        ArgMetadata::new(
+          "capability".to_string(),
+          "Capability such as `sigthief.apply`, `sigthief.*` or `level.admin`".to_string(),
+          ArgKind::String,
+          ArgRequirement::Optional,
+        ),
+      ],
+      Arc::new(|_bot, _msg, _cmd, _ctx| Box::pin(on_role(_bot, _msg, _cmd, _ctx))),
+    )
+    .with_capability("access.role");
+
+    let role_add_cmd = CommandMetadata::new(
+      Permission::OWNER,
+      "Assign a role to a user".to_string(),
+      ReplyRequirement::Optional,
+      role_assignment_args("assign"),
+      Arc::new(|_bot, _msg, _cmd, _ctx| Box::pin(on_role_add(_bot, _msg, _cmd, _ctx))),
+    )
+    .with_capability("access.role.assign")
+    .with_strict_args()
+    .with_opts(vec![global_opt()]);
+
+    let role_del_cmd = CommandMetadata::new(
+      Permission::OWNER,
+      "Take a role away from a user".to_string(),
+      ReplyRequirement::Optional,
+      role_assignment_args("unassign"),
+      Arc::new(|_bot, _msg, _cmd, _ctx| Box::pin(on_role_del(_bot, _msg, _cmd, _ctx))),
+    )
+    .with_capability("access.role.assign")
+    .with_strict_args()
+    .with_opts(vec![global_opt()]);
+
+    let ban_cmd = CommandMetadata::new(
+      Permission::OWNER,
+      "Make the bot ignore a user or chat everywhere".to_string(),
+      ReplyRequirement::Optional,
+      vec![
+        ban_target_arg(),
+        ArgMetadata::new(
+          "reason".to_string(),
+          "Why the ban was issued".to_string(),
+          ArgKind::Rest,
+          ArgRequirement::Optional,
+        ),
+      ],
+      Arc::new(|_bot, _msg, _cmd, _ctx| Box::pin(on_ban(_bot, _msg, _cmd, _ctx))),
+    )
+    .with_opts(vec![
+      ban_chat_opt(),
+      OptMetadata::new(
+        "for".to_string(),
+        Some('f'),
+        "Lift the ban automatically after this long".to_string(),
+        Some(ArgKind::Duration),
+      ),
+    ]);
+
+    let unban_cmd = CommandMetadata::new(
+      Permission::OWNER,
+      "Lift a ban on a user or chat".to_string(),
+      ReplyRequirement::Optional,
+      vec![ban_target_arg()],
+      Arc::new(|_bot, _msg, _cmd, _ctx| Box::pin(on_unban(_bot, _msg, _cmd, _ctx))),
+    )
+    .with_strict_args()
+    .with_opts(vec![ban_chat_opt()]);
+
+    let banlist_cmd = CommandMetadata::new(
+      Permission::OWNER,
+      "List banned users and chats".to_string(),
+      ReplyRequirement::None,
+      vec![],
+      Arc::new(|_bot, _msg, _cmd, _ctx| Box::pin(on_banlist(_bot, _msg, _cmd, _ctx))),
+    )
+    .with_aliases(&["bans"])
+    .with_global_scope();
+
+    let audit_cmd = CommandMetadata::new(
+      Permission::OWNER,
+      "Browse the audit log of permission changes and owner commands".to_string(),
+      ReplyRequirement::None,
+      vec![ArgMetadata::new(
+        "page".to_string(),
+        "Page to show, newest entries first".to_string(),
+        ArgKind::Integer,
+        ArgRequirement::Optional,
+      )],
+      Arc::new(|_bot, _msg, _cmd, _ctx| Box::pin(on_audit(_bot, _msg, _cmd, _ctx))),
+    )
+    .with_global_scope()
+    .with_opts(vec![
+      OptMetadata::new(
+        "user".to_string(),
+        Some('u'),
+        "Only entries by or about this user".to_string(),
+        Some(ArgKind::UserId),
+      ),
+      OptMetadata::new(
+        "command".to_string(),
+        Some('c'),
+        "Only entries caused by this command".to_string(),
+        Some(ArgKind::String),
+      ),
+      OptMetadata::new(
+        "since".to_string(),
+        Some('s'),
+        "Only entries newer than this, e.g. 1d".to_string(),
+        Some(ArgKind::Duration),
+      ),
+      OptMetadata::new(
+        "until".to_string(),
+        Some('t'),
+        "Only entries older than this, e.g. 2h".to_string(),
+        Some(ArgKind::Duration),
+      ),
+    ]);
+
+    let export_cmd = CommandMetadata::new(
+      Permission::OWNER,
+      "Export every permission entry as a JSON document".to_string(),
+      ReplyRequirement::None,
+      vec![],
+      Arc::new(|_bot, _msg, _cmd, _ctx| Box::pin(on_export(_bot, _msg, _cmd, _ctx))),
+    )
+    .with_capability("access.export");
+
+    let import_cmd = CommandMetadata::new(
+      Permission::OWNER,
+      "Preview or import permission entries from a replied JSON document".to_string(),
+      ReplyRequirement::Required,
+      vec![],
+      Arc::new(|_bot, _msg, _cmd, _ctx| Box::pin(on_import(_bot, _msg, _cmd, _ctx))),
+    )
+    .with_capability("access.import")
+    .with_opts(vec![
+      OptMetadata::new(
+        "apply".to_string(),
+        Some('a'),
+        "Write the changes instead of only previewing them".to_string(),
+        None,
+      ),
+      OptMetadata::new(
+        "replace".to_string(),
+        Some('r'),
+        "Remove entries missing from the document instead of keeping them".to_string(),
+        None,
+      ),
+    ]);
+
+    cmds.insert("pmgrant".to_string(), grant_cmd);
+    cmds.insert("pmrevoke".to_string(), revoke_cmd);
+    cmds.insert("pmset".to_string(), set_cmd);
+    cmds.insert("pmreset".to_string(), reset_cmd);
+    cmds.insert("pmshow".to_string(), show_cmd);
+    cmds.insert("pmdefault".to_string(), default_cmd);
+    cmds.insert("adminmap".to_string(), adminmap_cmd);
+    cmds.insert("cmdperm".to_string(), cmdperm_cmd);
+    cmds.insert("plugins".to_string(), plugins_cmd);
+    cmds.insert("plugin".to_string(), plugin_cmd);
+    cmds.insert("role".to_string(), role_cmd);
+    cmds.insert("roleadd".to_string(), role_add_cmd);
+    cmds.insert("roledel".to_string(), role_del_cmd);
+    cmds.insert("ban".to_string(), ban_cmd);
+    cmds.insert("unban".to_string(), unban_cmd);
+    cmds.insert("banlist".to_string(), banlist_cmd);
+    cmds.insert("audit".to_string(), audit_cmd);
+    cmds.insert("pmexport".to_string(), export_cmd);
+    cmds.insert("pmimport".to_string(), import_cmd);
+
+    cmds
+  }
+
+  fn update_handlers(&self) -> Vec<handler::UpdateHandler> {
+    Vec::new()
+  }
+}
+
+pub fn get_plugin() -> plugin::PluginBox {
   Box::new(Plugin::new())
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use r2d2::Pool;
+  use r2d2_sqlite::SqliteConnectionManager;
+
+  const CHAT: Scope = Scope::Chat(ChatId(-100));
+
+  fn settings() -> SettingsManager {
+    let pool = Pool::builder()
+      .max_size(1)
+      .build(SqliteConnectionManager::memory())
+      .unwrap();
+    SettingsManager::new(Arc::new(pool)).unwrap()
+  }
+
+  #[test]
+  fn global_plugin_changes_need_a_global_owner() {
+    let settings = settings();
+
+    for action in ["enable", "disable", "reset"] {
+      assert!(plugin_change_needs_global_owner(&settings, Scope::Global, action, "time").unwrap());
+    }
+  }
+
+  #[test]
+  fn enabling_a_globally_disabled_plugin_in_a_chat_needs_a_global_owner() {
+    let settings = settings();
+    settings.set_plugin_state(Scope::Global, "sigthief", false).unwrap();
+
+    assert!(plugin_change_needs_global_owner(&settings, CHAT, "enable", "sigthief").unwrap());
+    assert!(!plugin_change_needs_global_owner(&settings, CHAT, "disable", "sigthief").unwrap());
+    assert!(!plugin_change_needs_global_owner(&settings, CHAT, "reset", "sigthief").unwrap());
+  }
+
+  #[test]
+  fn chat_owners_manage_plugins_not_disabled_globally() {
+    let settings = settings();
+    assert!(!plugin_change_needs_global_owner(&settings, CHAT, "enable", "time").unwrap());
+
+    settings.set_plugin_state(Scope::Global, "time", true).unwrap();
+    assert!(!plugin_change_needs_global_owner(&settings, CHAT, "enable", "time").unwrap());
+    assert!(!plugin_change_needs_global_owner(&settings, CHAT, "disable", "time").unwrap());
+  }
+}
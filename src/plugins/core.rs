@@ -8,13 +8,14 @@ use teloxide::prelude::Requester;
 use teloxide::types::{Message, UserId};
 use teloxide::Bot;
 
+use crate::bot::args::ArgKind;
 use crate::bot::command::{self, ArgMetadata, ArgRequirement, CommandMetadata, ReplyRequirement};
-use crate::permissions::types::Permission;
+use crate::permissions::types::{Permission, Scope};
 
 use crate::{
-  bot::{context, handler, plugin},
+  bot::{context, dispatcher, handler, lexer, plugin},
   error,
-  utils::{formatter, metadata, style},
+  utils::{formatter, metadata, parsers, style},
 };
 
 #[derive(Error, Debug)]
@@ -23,7 +24,7 @@ pub enum CoreError {
   Internal(#[from] error::Error),
 
   #[error("usage: {0}")]
-  InvalidCommandUsage(String),
+  Usage(String),
 
   #[error("invalid {0}")]
   InvalidOption(String),
@@ -42,6 +43,9 @@ pub enum CoreError {
 
   #[error("{0} is empty")]
   IsEmpty(String),
+
+  #[error("permission denied: {0}")]
+  PermissionDenied(String),
 }
 
 async fn on_id(
@@ -89,81 +93,143 @@ async fn on_help(
 
   let ctx = match _ctx.upgrade() {
     Some(ctx) => ctx,
-    None => {
-      return Err(
-        error::emit(
-          Some(bot.clone()),
-          Some(msg.clone()),
-          error::Error::ContextDisposed,
-        )
-        .await,
-      )
-    }
+    None => return Err(error::Error::ContextDisposed.into()),
   };
 
   let style = style::get_style(_ctx.clone()).await;
 
   let ctx_guard = ctx.lock().await;
-  let dp_guard = ctx_guard.dp.lock().await;
-  let prefix = ctx_guard.cfg.lock().await.get_prefixes()[0];
+  let dp_guard = dispatcher::current(&ctx_guard.dp).await;
+  let prefix = ctx_guard.display_prefix_for(chat_id).await;
+  let case_insensitive = ctx_guard.cfg.read().await.is_case_insensitive();
 
-  let help_text = if let Some(command_name) = cmd.args.get(0) {
-    if let Some(info) = dp_guard.command_handlers.get(command_name) {
+  let help_text = if let Some(requested) = cmd.values.str("command") {
+    if let Some((command_name, info)) = dp_guard.resolve(requested, case_insensitive) {
       let args_desc: Vec<String> = info
         .args
         .iter()
         .map(|arg| {
           format!(
-            "{} {} [{}] → {}",
+            "{} {}: <i>{}</i> [{:?}] → {}",
             style.arrow(),
             arg.name,
-            format!("{:?}", arg.requirement),
+            arg.kind.describe(),
+            arg.requirement,
             arg.description
           )
         })
         .collect();
 
+      let opts_desc: Vec<String> = info
+        .opts
+        .iter()
+        .map(|opt| {
+          let short = opt.short.map(|c| format!(", -{}", c)).unwrap_or_default();
+          let kind = opt
+            .kind
+            .as_ref()
+            .map(|kind| kind.describe())
+            .unwrap_or_else(|| "flag".to_string());
+
+          format!(
+            "{} --{}{}: <i>{}</i> → {}",
+            style.arrow(),
+            opt.name,
+            short,
+            kind,
+            opt.description
+          )
+        })
+        .collect();
+
+      let opts_section = if opts_desc.is_empty() {
+        String::new()
+      } else {
+        format!("{} Options:\n{}\n", style.info(), opts_desc.join("\n"))
+      };
+
+      let scope = if info.global {
+        Scope::Global
+      } else {
+        Scope::Chat(chat_id)
+      };
+
+      let perm = match ctx_guard.perm_mgr.command_override(scope, command_name)? {
+        Some(ovr) => format!("{} (declared {:?})", ovr, info.perm),
+        None => format!("{:?}", info.perm),
+      };
+
+      let aliases = if info.aliases.is_empty() {
+        "none".to_string()
+      } else {
+        info
+          .aliases
+          .iter()
+          .map(|alias| format!("<code>{}{}</code>", prefix, alias))
+          .collect::<Vec<_>>()
+          .join(", ")
+      };
+
       format!(
         "{} Command: <code>{}{}</code>\n\
-      {} Permission: <b>{:?}</b>\n\
+      {} Plugin: <code>{}</code>\n\
+      {} Aliases: {}\n\
+      {} Usage: <code>{}</code>\n\
+      {} Permission: <b>{}</b>\n\
+      {} Capability: <code>{}</code>\n\
       {} Description: {}\n\
       {} Arguments:\n{}\n\
+      {}\
       {} Reply: <i>{:?}</i>",
         style.ok(),
         prefix,
         command_name,
         style.info(),
-        info.perm,
+        info.plugin,
+        style.info(),
+        aliases,
+        style.info(),
+        teloxide::utils::html::escape(&info.usage(&prefix, command_name)),
+        style.info(),
+        perm,
+        style.info(),
+        info.capability,
         style.info(),
         info.desc,
         style.info(),
-        args_desc.join(&format!("\n")),
+        args_desc.join("\n"),
+        opts_section,
         style.info(),
         info.reply
       )
     } else {
-      return Err(
-        error::emit(
-          Some(bot.clone()),
-          Some(msg.clone()),
-          CoreError::CommandNotFound(command_name.to_string()),
-        )
-        .await,
-      );
+      return Err(CoreError::CommandNotFound(requested.to_string()).into());
     }
   } else {
     let mut sections = Vec::new();
+    let settings = &ctx_guard.settings;
 
     for (plugin_name, plugin) in &dp_guard.plugins {
+      if !settings.is_plugin_enabled(Scope::Chat(chat_id), plugin_name)? {
+        continue;
+      }
+
       let commands_list: Vec<String> = plugin
         .commands()
         .iter()
         .map(|(name, info)| {
+          let aliases = if info.aliases.is_empty() {
+            String::new()
+          } else {
+            format!(" ({})", info.aliases.join(", "))
+          };
+
           format!(
-            "{} <code>{}{}</code> → {}",
+            "{} <code>{}{}</code>{} → {}",
             style.info(),
             prefix,
             name,
+            aliases,
             info.desc
           )
         })
@@ -181,6 +247,8 @@ async fn on_help(
     sections.join("\n\n")
   };
 
+  drop(ctx_guard);
+
   let _ = bot
     .send_message(chat_id, help_text)
     .parse_mode(teloxide::types::ParseMode::Html)
@@ -189,6 +257,80 @@ async fn on_help(
   Ok(())
 }
 
+async fn on_prefix(
+  bot: Bot,
+  msg: Message,
+  cmd: command::Command,
+  _ctx: Weak<tokio::sync::Mutex<context::Context>>,
+) -> anyhow::Result<()> {
+  let chat_id = msg.chat.id;
+
+  let ctx = match _ctx.upgrade() {
+    Some(ctx) => ctx,
+    None => return Err(error::Error::ContextDisposed.into()),
+  };
+
+  let style = style::get_style(_ctx.clone()).await;
+  let ctx_guard = ctx.lock().await;
+
+  match cmd.values.str("action").unwrap_or("show") {
+    "set" => {
+      let rest = cmd.values.str("prefixes").unwrap_or_default();
+      let tokens = lexer::tokenize(rest).map_err(|err| CoreError::Usage(err.to_string()))?;
+
+      let mut prefixes = Vec::new();
+      for token in &tokens {
+        match parsers::parse_prefix(&token.value).await {
+          Ok(prefix) => prefixes.push(prefix),
+          Err(_) => {
+            return Err(CoreError::InvalidOption(format!("prefix '{}'", token.value)).into());
+          }
+        }
+      }
+
+      if prefixes.is_empty() {
+        return Err(CoreError::OptionNotSpecified("prefixes".to_string()).into());
+      }
+
+      ctx_guard.settings.set_prefixes(chat_id, &prefixes)?;
+    }
+    "reset" => {
+      ctx_guard.settings.reset_prefixes(chat_id)?;
+    }
+    _ => {}
+  }
+
+  let is_custom = ctx_guard.settings.get_prefixes(chat_id)?.is_some();
+  let prefixes = ctx_guard.prefixes_for(chat_id).await;
+  let username = ctx_guard.cfg.read().await.get_username().map(str::to_string);
+  drop(ctx_guard);
+
+  let prefixes_list: Vec<String> = prefixes
+    .iter()
+    .map(|prefix| {
+      format!(
+        "{} <code>{}</code>",
+        style.info(),
+        teloxide::utils::html::escape(&format!("{:?}", prefix.display(username.as_deref())))
+      )
+    })
+    .collect();
+
+  let text = format!(
+    "{} <b>Prefixes</b> ({}):\n{}",
+    style.bullet(),
+    if is_custom { "chat" } else { "global" },
+    prefixes_list.join("\n")
+  );
+
+  let _ = bot
+    .send_message(chat_id, text)
+    .parse_mode(teloxide::types::ParseMode::Html)
+    .await;
+
+  Ok(())
+}
+
 async fn on_package(
   _bot: Bot,
   _msg: Message,
@@ -217,13 +359,28 @@ async fn on_shutdown(
   _ctx: Weak<tokio::sync::Mutex<context::Context>>,
 ) -> anyhow::Result<()> {
   let _style = style::get_style(_ctx.clone()).await;
-  let _msg_text = format!("{} Shuting down...", _style.arrow());
+  let _shutdown = match _ctx.upgrade() {
+    Some(ctx) => ctx.lock().await.shutdown.clone(),
+    None => return Err(error::Error::ContextDisposed.into()),
+  };
+
+  let _msg_text = format!(
+    "{} Shutting down, waiting up to {:?} for running commands...",
+    _style.arrow(),
+    _shutdown.deadline
+  );
   let _ = _bot
     .send_message(_msg.chat.id, _msg_text)
     .parse_mode(teloxide::types::ParseMode::Html)
     .await;
 
-  std::process::exit(0);
+  let _requested_by = _msg.from.as_ref().map(|user| user.id.to_string());
+  _shutdown.trigger(&format!(
+    "requested by user {}",
+    _requested_by.as_deref().unwrap_or("unknown")
+  ));
+
+  Ok(())
 }
 
 async fn on_ping(
@@ -256,6 +413,7 @@ async fn on_ping(
   Ok(())
 }
 
+#[derive(Default)]
 pub struct Plugin {}
 
 impl Plugin {
@@ -277,11 +435,7 @@ impl plugin::Plugin for Plugin {
       "Get chat and user identifiers".to_string(),
       ReplyRequirement::Optional,
       vec![],
-      Arc::new(|_bot, _msg, _cmd, _ctx| {
-        tokio::spawn(async move {
-          on_id(_bot, _msg, _cmd, _ctx).await.unwrap_or(());
-        });
-      }),
+      Arc::new(|_bot, _msg, _cmd, _ctx| Box::pin(on_id(_bot, _msg, _cmd, _ctx))),
     );
 
     let help_cmd = CommandMetadata::new(
@@ -291,49 +445,62 @@ impl plugin::Plugin for Plugin {
       vec![ArgMetadata::new(
         "command".to_string(),
         "Command name to get detailed info".to_string(),
+        ArgKind::String,
         ArgRequirement::Optional,
       )],
-      Arc::new(|_bot, _msg, _cmd, _ctx| {
-        tokio::spawn(async move {
-          on_help(_bot, _msg, _cmd, _ctx).await.unwrap_or(());
-        });
-      }),
-    );
+      Arc::new(|_bot, _msg, _cmd, _ctx| Box::pin(on_help(_bot, _msg, _cmd, _ctx))),
+    )
+    .with_aliases(&["h"]);
 
     let shutdown_cmd = CommandMetadata::new(
       Permission::OWNER,
       "Shutdown the bot process".to_string(),
       ReplyRequirement::None,
       vec![],
-      Arc::new(|_bot, _msg, _cmd, _ctx| {
-        tokio::spawn(async move {
-          on_shutdown(_bot, _msg, _cmd, _ctx).await.unwrap_or(());
-        });
-      }),
-    );
+      Arc::new(|_bot, _msg, _cmd, _ctx| Box::pin(on_shutdown(_bot, _msg, _cmd, _ctx))),
+    )
+    .with_global_scope();
 
     let package_cmd = CommandMetadata::new(
       Permission::USER,
       "Display bot version and package information".to_string(),
       ReplyRequirement::None,
       vec![],
-      Arc::new(|_bot, _msg, _cmd, _ctx| {
-        tokio::spawn(async move {
-          on_package(_bot, _msg, _cmd, _ctx).await.unwrap_or(());
-        });
-      }),
-    );
+      Arc::new(|_bot, _msg, _cmd, _ctx| Box::pin(on_package(_bot, _msg, _cmd, _ctx))),
+    )
+    .with_aliases(&["version"]);
 
     let ping_cmd = CommandMetadata::new(
       Permission::USER,
       "Check bot response time and latency".to_string(),
       ReplyRequirement::None,
       vec![],
-      Arc::new(|_bot, _msg, _cmd, _ctx| {
-        tokio::spawn(async move {
-          on_ping(_bot, _msg, _cmd, _ctx).await.unwrap_or(());
-        });
-      }),
+      Arc::new(|_bot, _msg, _cmd, _ctx| Box::pin(on_ping(_bot, _msg, _cmd, _ctx))),
+    );
+
+    let prefix_cmd = CommandMetadata::new(
+      Permission::ADMIN,
+      "Show, set or reset the command prefixes of this chat".to_string(),
+      ReplyRequirement::None,
+      vec![
+        ArgMetadata::new(
+          "action".to_string(),
+          "What to do with the prefixes".to_string(),
+          ArgKind::Choice(vec![
+            "show".to_string(),
+            "set".to_string(),
+            "reset".to_string(),
+          ]),
+          ArgRequirement::Optional,
+        ),
+        ArgMetadata::new(
+          "prefixes".to_string(),
+          "New prefixes, quoted if they contain spaces; @mention for the bot's username".to_string(),
+          ArgKind::Rest,
+          ArgRequirement::Optional,
+        ),
+      ],
+      Arc::new(|_bot, _msg, _cmd, _ctx| Box::pin(on_prefix(_bot, _msg, _cmd, _ctx))),
     );
 
     cmds.insert("id".to_string(), id_cmd);
@@ -341,6 +508,7 @@ impl plugin::Plugin for Plugin {
     cmds.insert("shutdown".to_string(), shutdown_cmd);
     cmds.insert("package".to_string(), package_cmd);
     cmds.insert("ping".to_string(), ping_cmd);
+    cmds.insert("prefix".to_string(), prefix_cmd);
 
     cmds
   }
@@ -1,17 +1,30 @@
-pub mod access;
+// `core` also holds the error type shared by the other plugins, so it is
+// always compiled; its feature only decides whether it is registered.
 pub mod core;
+
+#[cfg(feature = "plugin-access")]
+pub mod access;
+#[cfg(feature = "plugin-sigthief")]
 pub mod sigthief;
+#[cfg(feature = "plugin-system")]
 pub mod system;
+#[cfg(feature = "plugin-time")]
 pub mod time;
 
 use crate::bot::plugin;
 
+/// Every built-in plugin enabled by cargo features.
 pub async fn all() -> Vec<plugin::PluginBox> {
   vec![
+    #[cfg(feature = "plugin-core")]
     core::get_plugin(),
+    #[cfg(feature = "plugin-access")]
     access::get_plugin(),
+    #[cfg(feature = "plugin-time")]
     time::get_plugin(),
+    #[cfg(feature = "plugin-system")]
     system::get_plugin(),
+    #[cfg(feature = "plugin-sigthief")]
     sigthief::get_plugin(),
   ]
 }
@@ -1,4 +1,4 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Weak};
 
 use indexmap::IndexMap;
@@ -11,8 +11,8 @@ use teloxide::Bot;
 
 use tokio::io::AsyncWriteExt;
 
+use crate::bot::args::ArgKind;
 use crate::bot::command::{self, ArgMetadata, CommandMetadata, ReplyRequirement};
-use crate::error;
 use crate::permissions::types::Permission;
 use crate::utils::dirs;
 
@@ -22,6 +22,35 @@ use crate::{
   utils::style,
 };
 
+/// Directory the files sent to the bot are downloaded to while they are
+/// being worked on. Removed when the plugin stops, so it is per process to
+/// leave other instances on the same host alone.
+fn temp_root() -> PathBuf {
+  std::env::temp_dir().join(format!("tebot-sigthief-{}", std::process::id()))
+}
+
+/// Creates a directory for the files of a single command, so concurrent
+/// commands on files with the same name do not clash.
+async fn temp_dir_for(msg: &Message) -> anyhow::Result<PathBuf> {
+  let dir = temp_root().join(format!("{}-{}", msg.chat.id, msg.id));
+  tokio::fs::create_dir_all(&dir).await?;
+  Ok(dir)
+}
+
+async fn remove_temp_dir(dir: &Path) {
+  if let Err(err) = tokio::fs::remove_dir_all(dir).await {
+    log::warn!("failed to remove {}: {:?}", dir.display(), err);
+  }
+}
+
+/// Keeps only the last component of a user-supplied file name.
+fn sanitize_filename(name: Option<&str>, fallback: &str) -> String {
+  name
+    .and_then(|name| Path::new(name).file_name())
+    .map(|name| name.to_string_lossy().into_owned())
+    .unwrap_or_else(|| fallback.to_string())
+}
+
 async fn download_file(
   bot: &Bot,
   file_id: FileId,
@@ -50,25 +79,10 @@ async fn on_extract(
 
   let _file = match _file {
     Some(f) => f,
-    None => {
-      return Err(
-        error::emit(
-          Some(_bot.clone()),
-          Some(_msg.clone()),
-          CoreError::OptionNotSpecified("reply".to_string()),
-        )
-        .await,
-      )
-    }
+    None => return Err(CoreError::OptionNotSpecified("reply".to_string()).into()),
   };
 
-  let _filename = &_file
-    .file_name
-    .clone()
-    .unwrap_or("unnammed.dll".to_string());
-
-  let mut _path = std::env::temp_dir();
-  _path.push(_filename);
+  let _filename = &sanitize_filename(_file.file_name.as_deref(), "unnammed.dll");
 
   let mut _extract_msg = _bot
     .send_message(
@@ -78,15 +92,21 @@ async fn on_extract(
     .parse_mode(ParseMode::Html)
     .await?;
 
-  download_file(&_bot, _file.file.id.clone(), &_path).await?;
+  let _tmp = temp_dir_for(&_msg).await?;
+  let _path = _tmp.join(_filename);
+
+  let _sig = async {
+    download_file(&_bot, _file.file.id.clone(), &_path).await?;
 
-  let _sig = tokio::task::spawn_blocking({
     let _path = _path.clone();
-    move || sigthief::extract_signature(&_path)
-  })
-  .await?;
+    let _sig = tokio::task::spawn_blocking(move || sigthief::extract_signature(&_path)).await?;
+    anyhow::Ok(_sig)
+  }
+  .await;
+
+  remove_temp_dir(&_tmp).await;
 
-  match _sig {
+  match _sig? {
     Ok(_sig) => {
       let _dir = dirs::plugin_data_dir(get_plugin()).await?;
       tokio::fs::create_dir_all(std::path::Path::new(&_dir)).await?;
@@ -110,9 +130,7 @@ async fn on_extract(
         .parse_mode(ParseMode::Html)
         .await?;
     }
-    Err(e) => {
-      return Err(error::emit(Some(_bot.clone()), Some(_msg.clone()), e).await);
-    }
+    Err(e) => return Err(e.into()),
   }
 
   Ok(())
@@ -134,25 +152,15 @@ async fn on_apply(
 
   let _file = match _file {
     Some(f) => f,
-    None => {
-      return Err(
-        error::emit(
-          Some(_bot.clone()),
-          Some(_msg.clone()),
-          CoreError::OptionNotSpecified("reply".to_string()),
-        )
-        .await,
-      )
-    }
+    None => return Err(CoreError::OptionNotSpecified("reply".to_string()).into()),
   };
 
-  let _filename = &_file
-    .file_name
-    .clone()
-    .unwrap_or("unnammed.signed.exe".to_string());
+  let _filename = &sanitize_filename(_file.file_name.as_deref(), "unnammed.signed.exe");
 
-  let mut _path = std::env::temp_dir();
-  _path.push(_filename);
+  let _signature = match _cmd.values.str("signature") {
+    Some(s) => s.to_string(),
+    None => return Err(CoreError::OptionNotSpecified("signature".to_string()).into()),
+  };
 
   let mut _apply_msg = _bot
     .send_message(
@@ -162,46 +170,35 @@ async fn on_apply(
     .parse_mode(ParseMode::Html)
     .await?;
 
-  download_file(&_bot, _file.file.id.clone(), &_path).await?;
+  let _tmp = temp_dir_for(&_msg).await?;
+  let _path = _tmp.join(_filename);
+
+  let _result = async {
+    download_file(&_bot, _file.file.id.clone(), &_path).await?;
 
-  if let Some(_signature) = _cmd.args.get(0) {
-    let _result = tokio::task::spawn_blocking({
+    tokio::task::spawn_blocking({
       let _path = _path.clone();
-      let _signature = _signature.clone();
       move || {
         sigthief::load_signature(&_signature)
           .and_then(|_sig| sigthief::apply_signature(&_path, &_sig))
       }
     })
-    .await?;
+    .await??;
 
-    match _result {
-      Ok(_) => {
-        _bot.delete_message(_msg.chat.id, _apply_msg.id).await?;
-        _bot
-          .send_document(_msg.chat.id, InputFile::file(_path.clone()))
-          .caption(format!("{} <b>signature applied</b>", _style.bullet(),))
-          .parse_mode(ParseMode::Html)
-          .await?;
+    _bot.delete_message(_msg.chat.id, _apply_msg.id).await?;
+    _bot
+      .send_document(_msg.chat.id, InputFile::file(_path.clone()))
+      .caption(format!("{} <b>signature applied</b>", _style.bullet(),))
+      .parse_mode(ParseMode::Html)
+      .await?;
 
-        tokio::fs::remove_file(_path.clone()).await?;
-      }
-      Err(e) => {
-        return Err(error::emit(Some(_bot.clone()), Some(_msg.clone()), e).await);
-      }
-    }
-  } else {
-    return Err(
-      error::emit(
-        Some(_bot.clone()),
-        Some(_msg.clone()),
-        CoreError::OptionNotSpecified("signature".to_string()),
-      )
-      .await,
-    );
+    anyhow::Ok(())
   }
+  .await;
 
-  Ok(())
+  remove_temp_dir(&_tmp).await;
+
+  _result
 }
 
 async fn on_list(
@@ -230,6 +227,7 @@ async fn on_list(
   Ok(())
 }
 
+#[derive(Default)]
 pub struct Plugin {}
 
 impl Plugin {
@@ -251,12 +249,9 @@ impl plugin::Plugin for Plugin {
       "Extract the digital signature from a PE file and save it for later use".to_string(),
       ReplyRequirement::Required,
       vec![],
-      Arc::new(|_bot, _msg, _cmd, _ctx| {
-        tokio::spawn(async move {
-          on_extract(_bot, _msg, _cmd, _ctx).await.unwrap_or(());
-        });
-      }),
-    );
+      Arc::new(|_bot, _msg, _cmd, _ctx| Box::pin(on_extract(_bot, _msg, _cmd, _ctx))),
+    )
+    .with_capability("sigthief.extract");
 
     let apply_cmd = CommandMetadata::new(
       Permission::ADMIN,
@@ -265,26 +260,21 @@ impl plugin::Plugin for Plugin {
       vec![ArgMetadata::new(
         "signature".to_string(),
         "The name of the signature file to apply".to_string(),
+        ArgKind::String,
         command::ArgRequirement::Required,
       )],
-      Arc::new(|_bot, _msg, _cmd, _ctx| {
-        tokio::spawn(async move {
-          on_apply(_bot, _msg, _cmd, _ctx).await.unwrap_or(());
-        });
-      }),
-    );
+      Arc::new(|_bot, _msg, _cmd, _ctx| Box::pin(on_apply(_bot, _msg, _cmd, _ctx))),
+    )
+    .with_capability("sigthief.apply");
 
     let list_cmd = CommandMetadata::new(
       Permission::ADMIN,
       "List all saved digital signature files available for use".to_string(),
       ReplyRequirement::None,
       vec![],
-      Arc::new(|_bot, _msg, _cmd, _ctx| {
-        tokio::spawn(async move {
-          on_list(_bot, _msg, _cmd, _ctx).await.unwrap_or(());
-        });
-      }),
-    );
+      Arc::new(|_bot, _msg, _cmd, _ctx| Box::pin(on_list(_bot, _msg, _cmd, _ctx))),
+    )
+    .with_capability("sigthief.list");
 
     cmds.insert("sigextract".to_string(), extract_cmd);
     cmds.insert("sigapply".to_string(), apply_cmd);
@@ -296,6 +286,19 @@ impl plugin::Plugin for Plugin {
   fn update_handlers(&self) -> Vec<handler::UpdateHandler> {
     Vec::new()
   }
+
+  /// Removes files left behind by commands that were aborted mid-way.
+  fn on_stop<'a>(
+    &'a self,
+    _ctx: &'a context::Context,
+  ) -> handler::HookFuture<'a> {
+    Box::pin(async {
+      match tokio::fs::remove_dir_all(temp_root()).await {
+        Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(err.into()),
+        _ => Ok(()),
+      }
+    })
+  }
 }
 
 pub fn get_plugin() -> plugin::PluginBox {
@@ -6,9 +6,10 @@ use indexmap::IndexMap;
 use teloxide::payloads::*;
 use teloxide::prelude::Requester;
 use teloxide::types::Message;
+use teloxide::utils::html;
 use teloxide::Bot;
 
-use crate::bot::command::{self, CommandMetadata, ReplyRequirement};
+use crate::bot::command::{self, CommandMetadata, OptMetadata, ReplyRequirement};
 use crate::permissions::types::Permission;
 
 use crate::{
@@ -49,6 +50,32 @@ async fn on_sysinfo(
   let _os_version = System::os_version().unwrap_or_else(|| "Unknown".to_string());
   let _kernel_version = System::kernel_version().unwrap_or_else(|| "Unknown".to_string());
 
+  if _cmd.values.flag("json") {
+    let _json = serde_json::json!({
+      "os": { "name": _os_name, "version": _os_version, "kernel": _kernel_version },
+      "cpu": { "brand": _cpu_brand, "cores": _cpu_count, "usage": _cpu_usage },
+      "memory": {
+        "total_mb": _total_memory,
+        "used_mb": _used_memory,
+        "usage": _memory_usage,
+        "process_mb": _process_memory,
+      },
+    });
+
+    let _ = _bot
+      .send_message(
+        _msg.chat.id,
+        format!(
+          "<pre>{}</pre>",
+          html::escape(&serde_json::to_string_pretty(&_json)?)
+        ),
+      )
+      .parse_mode(teloxide::types::ParseMode::Html)
+      .await;
+
+    return Ok(());
+  }
+
   let _msg_text = format!(
     "{} <b>System Information</b>\n\n\
     {} <b>OS</b>: {} {}\n\
@@ -88,6 +115,7 @@ async fn on_sysinfo(
   Ok(())
 }
 
+#[derive(Default)]
 pub struct Plugin {}
 
 impl Plugin {
@@ -109,12 +137,15 @@ impl plugin::Plugin for Plugin {
       "Display system resources and host information".to_string(),
       ReplyRequirement::None,
       vec![],
-      Arc::new(|_bot, _msg, _cmd, _ctx| {
-        tokio::spawn(async move {
-          on_sysinfo(_bot, _msg, _cmd, _ctx).await.unwrap_or(());
-        });
-      }),
-    );
+      Arc::new(|_bot, _msg, _cmd, _ctx| Box::pin(on_sysinfo(_bot, _msg, _cmd, _ctx))),
+    )
+    .with_aliases(&["sys"])
+    .with_opts(vec![OptMetadata::new(
+      "json".to_string(),
+      Some('j'),
+      "Output the report as JSON".to_string(),
+      None,
+    )]);
 
     cmds.insert("sysinfo".to_string(), sysinfo_cmd);
 
@@ -80,6 +80,7 @@ async fn on_datetime(
   Ok(())
 }
 
+#[derive(Default)]
 pub struct Plugin {}
 
 impl Plugin {
@@ -101,24 +102,18 @@ impl plugin::Plugin for Plugin {
       "Display bot uptime since last restart".to_string(),
       ReplyRequirement::None,
       vec![],
-      Arc::new(|_bot, _msg, _cmd, _ctx| {
-        tokio::spawn(async move {
-          on_uptime(_bot, _msg, _cmd, _ctx).await.unwrap_or(());
-        });
-      }),
-    );
+      Arc::new(|_bot, _msg, _cmd, _ctx| Box::pin(on_uptime(_bot, _msg, _cmd, _ctx))),
+    )
+    .with_aliases(&["up"]);
 
     let datetime_cmd = CommandMetadata::new(
       Permission::USER,
       "Display current date and time in multiple formats".to_string(),
       ReplyRequirement::None,
       vec![],
-      Arc::new(|_bot, _msg, _cmd, _ctx| {
-        tokio::spawn(async move {
-          on_datetime(_bot, _msg, _cmd, _ctx).await.unwrap_or(());
-        });
-      }),
-    );
+      Arc::new(|_bot, _msg, _cmd, _ctx| Box::pin(on_datetime(_bot, _msg, _cmd, _ctx))),
+    )
+    .with_aliases(&["date", "time"]);
 
     cmds.insert("uptime".to_string(), uptime_cmd);
     cmds.insert("datetime".to_string(), datetime_cmd);
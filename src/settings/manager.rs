@@ -0,0 +1,239 @@
+use rusqlite::{params, OptionalExtension};
+use std::sync::Arc;
+
+use r2d2::Pool;
+use r2d2_sqlite::SqliteConnectionManager;
+
+use teloxide::types::ChatId;
+
+use crate::bot::command::Prefix;
+use crate::permissions::types::Scope;
+
+#[derive(Debug, Clone)]
+pub struct SettingsManager {
+  pub db: Arc<Pool<SqliteConnectionManager>>,
+}
+
+impl SettingsManager {
+  pub fn new(db: Arc<Pool<SqliteConnectionManager>>) -> anyhow::Result<Self> {
+    let mgr = Self { db };
+    mgr.init_schema()?;
+    Ok(mgr)
+  }
+
+  pub fn new_shared(db: Arc<Pool<SqliteConnectionManager>>) -> anyhow::Result<Arc<Self>> {
+    let mgr = Self::new(db)?;
+    Ok(Arc::new(mgr))
+  }
+
+  fn init_schema(&self) -> anyhow::Result<()> {
+    let conn = self.db.get()?;
+    conn.execute(
+      "CREATE TABLE IF NOT EXISTS chat_settings (
+                chat_id  INTEGER PRIMARY KEY,
+                prefixes TEXT
+            )",
+      [],
+    )?;
+    Self::ensure_column(&conn, "admin_mapping", "INTEGER NOT NULL DEFAULT 0")?;
+    conn.execute(
+      "CREATE TABLE IF NOT EXISTS plugin_states (
+                chat_id INTEGER NOT NULL DEFAULT 0,
+                plugin  TEXT NOT NULL,
+                enabled INTEGER NOT NULL,
+                PRIMARY KEY (chat_id, plugin)
+            )",
+      [],
+    )?;
+    Ok(())
+  }
+
+  /// Adds a column to `chat_settings` if a database from an older version
+  /// lacks it.
+  fn ensure_column(
+    conn: &rusqlite::Connection,
+    name: &str,
+    decl: &str,
+  ) -> anyhow::Result<()> {
+    let columns: Vec<String> = conn
+      .prepare("PRAGMA table_info(chat_settings)")?
+      .query_map([], |row| row.get(1))?
+      .collect::<Result<_, _>>()?;
+
+    if !columns.iter().any(|c| c == name) {
+      log::info!("adding column {} to chat_settings", name);
+      conn.execute(
+        &format!("ALTER TABLE chat_settings ADD COLUMN {} {}", name, decl),
+        [],
+      )?;
+    }
+
+    Ok(())
+  }
+
+  pub fn get_prefixes(&self, chat_id: ChatId) -> anyhow::Result<Option<Vec<Prefix>>> {
+    let conn = self.db.get()?;
+    let raw: Option<String> = conn
+      .query_row(
+        "SELECT prefixes FROM chat_settings WHERE chat_id = ?1",
+        params![chat_id.0],
+        |row| row.get(0),
+      )
+      .optional()?
+      .flatten();
+
+    let prefixes = match raw {
+      Some(raw) => Some(serde_json::from_str(&raw)?),
+      None => None,
+    };
+
+    log::trace!("get prefixes for chat {}: {:?}", chat_id, prefixes);
+
+    Ok(prefixes)
+  }
+
+  pub fn set_prefixes(&self, chat_id: ChatId, prefixes: &[Prefix]) -> anyhow::Result<()> {
+    let conn = self.db.get()?;
+    conn.execute(
+      "INSERT INTO chat_settings (chat_id, prefixes)
+             VALUES (?1, ?2)
+             ON CONFLICT(chat_id) DO UPDATE SET prefixes = excluded.prefixes",
+      params![chat_id.0, serde_json::to_string(prefixes)?],
+    )?;
+
+    log::trace!("set prefixes for chat {}: {:?}", chat_id, prefixes);
+
+    Ok(())
+  }
+
+  /// Whether Telegram chat administrators get tebot permissions in the chat.
+  pub fn get_admin_mapping(&self, chat_id: ChatId) -> anyhow::Result<bool> {
+    let conn = self.db.get()?;
+    let enabled = conn
+      .query_row(
+        "SELECT admin_mapping FROM chat_settings WHERE chat_id = ?1",
+        params![chat_id.0],
+        |row| row.get::<_, bool>(0),
+      )
+      .optional()?
+      .unwrap_or(false);
+
+    log::trace!("get admin mapping for chat {}: {}", chat_id, enabled);
+
+    Ok(enabled)
+  }
+
+  pub fn set_admin_mapping(&self, chat_id: ChatId, enabled: bool) -> anyhow::Result<()> {
+    let conn = self.db.get()?;
+    conn.execute(
+      "INSERT INTO chat_settings (chat_id, admin_mapping)
+             VALUES (?1, ?2)
+             ON CONFLICT(chat_id) DO UPDATE SET admin_mapping = excluded.admin_mapping",
+      params![chat_id.0, enabled],
+    )?;
+
+    log::trace!("set admin mapping for chat {}: {}", chat_id, enabled);
+
+    Ok(())
+  }
+
+  /// Whether a plugin is switched on or off in exactly this scope, if it is.
+  pub fn get_plugin_state(
+    &self,
+    scope: Scope,
+    plugin: &str,
+  ) -> anyhow::Result<Option<bool>> {
+    let conn = self.db.get()?;
+    let enabled = conn
+      .query_row(
+        "SELECT enabled FROM plugin_states WHERE chat_id = ?1 AND plugin = ?2",
+        params![scope.key(), plugin],
+        |row| row.get::<_, bool>(0),
+      )
+      .optional()?;
+
+    Ok(enabled)
+  }
+
+  /// Finds the state deciding whether a plugin runs in a scope: the chat's
+  /// own, then the global one. Returns the scope it came from.
+  pub fn resolve_plugin_state(
+    &self,
+    scope: Scope,
+    plugin: &str,
+  ) -> anyhow::Result<Option<(Scope, bool)>> {
+    if let Scope::Chat(_) = scope
+      && let Some(enabled) = self.get_plugin_state(scope, plugin)?
+    {
+      return Ok(Some((scope, enabled)));
+    }
+
+    Ok(
+      self
+        .get_plugin_state(Scope::Global, plugin)?
+        .map(|enabled| (Scope::Global, enabled)),
+    )
+  }
+
+  /// Plugins are enabled unless switched off in the chat or globally.
+  pub fn is_plugin_enabled(
+    &self,
+    scope: Scope,
+    plugin: &str,
+  ) -> anyhow::Result<bool> {
+    let enabled = self
+      .resolve_plugin_state(scope, plugin)?
+      .is_none_or(|(_, enabled)| enabled);
+
+    log::trace!("plugin {} enabled in {}: {}", plugin, scope, enabled);
+
+    Ok(enabled)
+  }
+
+  pub fn set_plugin_state(
+    &self,
+    scope: Scope,
+    plugin: &str,
+    enabled: bool,
+  ) -> anyhow::Result<()> {
+    let conn = self.db.get()?;
+    conn.execute(
+      "INSERT INTO plugin_states (chat_id, plugin, enabled)
+             VALUES (?1, ?2, ?3)
+             ON CONFLICT(chat_id, plugin) DO UPDATE SET enabled = excluded.enabled",
+      params![scope.key(), plugin, enabled],
+    )?;
+
+    log::trace!("set plugin {} in {}: enabled={}", plugin, scope, enabled);
+
+    Ok(())
+  }
+
+  pub fn reset_plugin_state(
+    &self,
+    scope: Scope,
+    plugin: &str,
+  ) -> anyhow::Result<()> {
+    let conn = self.db.get()?;
+    conn.execute(
+      "DELETE FROM plugin_states WHERE chat_id = ?1 AND plugin = ?2",
+      params![scope.key(), plugin],
+    )?;
+
+    log::trace!("reset state of plugin {} in {}", plugin, scope);
+
+    Ok(())
+  }
+
+  pub fn reset_prefixes(&self, chat_id: ChatId) -> anyhow::Result<()> {
+    let conn = self.db.get()?;
+    conn.execute(
+      "UPDATE chat_settings SET prefixes = NULL WHERE chat_id = ?1",
+      params![chat_id.0],
+    )?;
+
+    log::trace!("reset prefixes for chat {}", chat_id);
+
+    Ok(())
+  }
+}
@@ -1,29 +1,93 @@
 use std::env;
+use std::time::Duration;
 
 use teloxide::types::UserId;
 
 use super::parsers;
+use crate::bot::command::Prefix;
+use crate::permissions::types::Permission;
 
 pub async fn get_token() -> String {
   env::var("BOT_TOKEN").expect("BOT_TOKEN not set")
 }
 
 pub async fn get_db_path() -> String {
-  let db_path = env::var("DB_PATH").unwrap_or_else(|_| "database.db".to_string());
-  db_path
+  env::var("DB_PATH").unwrap_or_else(|_| "database.db".to_string())
 }
 
 pub async fn get_data_dir() -> String {
-  let db_path = env::var("DATA_DIR").unwrap_or_else(|_| "data".to_string());
-  db_path
+  env::var("DATA_DIR").unwrap_or_else(|_| "data".to_string())
 }
 
-pub async fn get_prefixes() -> Vec<char> {
-  let prefixes: Vec<char> = env::var("PREFIXES")
-    .unwrap_or_else(|_| "/".to_string())
-    .chars()
-    .collect();
-  prefixes
+/// Reads `PREFIXES`. Every character is a prefix of its own (e.g. `/!`),
+/// unless `PREFIX_SEPARATOR` is set, in which case `PREFIXES` is a list split
+/// by it, e.g. `/,!!,tb ,@mention` with `PREFIX_SEPARATOR=,`. The `@mention`
+/// entry enables addressing the bot by its username.
+pub async fn get_prefixes() -> Vec<Prefix> {
+  let raw = env::var("PREFIXES").unwrap_or_else(|_| "/".to_string());
+
+  let parts: Vec<String> = match env::var("PREFIX_SEPARATOR") {
+    Ok(separator) if !separator.is_empty() => {
+      raw.split(separator.as_str()).map(str::to_string).collect()
+    }
+    _ => raw.chars().map(String::from).collect(),
+  };
+
+  let mut prefixes = Vec::new();
+  for part in &parts {
+    if let Ok(prefix) = parsers::parse_prefix(part).await {
+      prefixes.push(prefix);
+    }
+  }
+
+  if prefixes.is_empty() {
+    vec![Prefix::Literal("/".to_string())]
+  } else {
+    prefixes
+  }
+}
+
+pub async fn get_case_insensitive() -> bool {
+  env::var("CASE_INSENSITIVE")
+    .map(|v| matches!(v.trim().to_lowercase().as_str(), "1" | "true" | "yes"))
+    .unwrap_or(false)
+}
+
+/// Permission of users without an explicit entry, `none` unless
+/// DEFAULT_PERMISSION says otherwise.
+pub async fn get_default_permission() -> Permission {
+  let raw = env::var("DEFAULT_PERMISSION").unwrap_or_else(|_| "none".to_string());
+  parsers::parse_permission(&raw).await.unwrap_or_else(|err| {
+    log::warn!("invalid DEFAULT_PERMISSION '{}': {}, using none", raw, err);
+    Permission::NONE
+  })
+}
+
+/// How long a chat's administrator list is trusted before it is fetched again.
+pub async fn get_admin_cache_ttl() -> Duration {
+  let raw = env::var("ADMIN_CACHE_TTL").unwrap_or_else(|_| "5m".to_string());
+  parsers::parse_duration(&raw).await.unwrap_or_else(|err| {
+    log::warn!("invalid ADMIN_CACHE_TTL '{}': {}, using 5m", raw, err);
+    Duration::from_secs(300)
+  })
+}
+
+/// How often expired temporary grants are swept.
+pub async fn get_grant_sweep_interval() -> Duration {
+  let raw = env::var("GRANT_SWEEP_INTERVAL").unwrap_or_else(|_| "1m".to_string());
+  parsers::parse_duration(&raw).await.unwrap_or_else(|err| {
+    log::warn!("invalid GRANT_SWEEP_INTERVAL '{}': {}, using 1m", raw, err);
+    Duration::from_secs(60)
+  })
+}
+
+/// How long shutdown waits for running commands before aborting them.
+pub async fn get_shutdown_timeout() -> Duration {
+  let raw = env::var("SHUTDOWN_TIMEOUT").unwrap_or_else(|_| "30s".to_string());
+  parsers::parse_duration(&raw).await.unwrap_or_else(|err| {
+    log::warn!("invalid SHUTDOWN_TIMEOUT '{}': {}, using 30s", raw, err);
+    Duration::from_secs(30)
+  })
 }
 
 pub async fn get_owner_id() -> anyhow::Result<UserId> {
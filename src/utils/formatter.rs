@@ -33,3 +33,14 @@ pub fn format_duration(dur: Duration) -> String {
     format!("{}s", seconds)
   }
 }
+
+/// Shortens `s` to at most `max` characters, marking the cut with `…`.
+pub fn truncate(s: &str, max: usize) -> String {
+  if s.chars().count() <= max {
+    return s.to_string();
+  }
+
+  let mut cut: String = s.chars().take(max.saturating_sub(1)).collect();
+  cut.push('…');
+  cut
+}
@@ -1,4 +1,5 @@
 use std::env;
+use std::str::FromStr;
 
 #[derive(Debug)]
 pub struct Version {
@@ -7,8 +8,10 @@ pub struct Version {
   pub patch: u64,
 }
 
-impl Version {
-  pub fn from_str(s: &str) -> anyhow::Result<Self> {
+impl FromStr for Version {
+  type Err = anyhow::Error;
+
+  fn from_str(s: &str) -> anyhow::Result<Self> {
     let parts: Vec<&str> = s.split('.').collect();
     if parts.len() != 3 {
       return Err(anyhow::anyhow!("invalid version string: '{}'", s));
@@ -1,6 +1,9 @@
+use std::time::Duration;
+
 use anyhow::{anyhow, Context};
 use teloxide::prelude::UserId;
 
+use crate::bot::command::Prefix;
 use crate::permissions::types::{Permission, PermissionMap};
 
 pub async fn parse_permission(s: &str) -> anyhow::Result<Permission> {
@@ -15,6 +18,9 @@ pub async fn parse_permission(s: &str) -> anyhow::Result<Permission> {
   let mut role_mask = Permission::NONE;
   for role_str in s.split('|') {
     match role_str.to_uppercase().trim() {
+      "NONE" => {
+        log::trace!("'none' adds no permission");
+      }
       "USER" => {
         role_mask |= Permission::USER;
         log::trace!("added 'user' permission");
@@ -38,6 +44,16 @@ pub async fn parse_permission(s: &str) -> anyhow::Result<Permission> {
   Ok(role_mask)
 }
 
+/// Parses a single prefix; `@mention` selects addressing by bot username.
+pub async fn parse_prefix(s: &str) -> anyhow::Result<Prefix> {
+  log::trace!("parsing prefix from string '{}'", s);
+  match s {
+    "" => Err(anyhow!("empty prefix string")),
+    "@mention" => Ok(Prefix::Mention),
+    literal => Ok(Prefix::Literal(literal.to_string())),
+  }
+}
+
 pub async fn parse_uid(s: &str) -> anyhow::Result<UserId> {
   log::trace!("parsing user id from string '{}'", s);
   s.parse::<u64>()
@@ -45,9 +61,53 @@ pub async fn parse_uid(s: &str) -> anyhow::Result<UserId> {
     .map_err(|_| anyhow!("invalid user id: '{}'", s))
 }
 
+pub async fn parse_duration(s: &str) -> anyhow::Result<Duration> {
+  let s = s.trim();
+  log::trace!("parsing duration from string '{}'", s);
+
+  if s.is_empty() {
+    return Err(anyhow!("empty duration string"));
+  }
+
+  let mut total = 0u64;
+  let mut number = String::new();
+
+  for c in s.chars() {
+    if c.is_ascii_digit() {
+      number.push(c);
+      continue;
+    }
+
+    let unit = match c.to_ascii_lowercase() {
+      's' => 1,
+      'm' => 60,
+      'h' => 60 * 60,
+      'd' => 24 * 60 * 60,
+      'w' => 7 * 24 * 60 * 60,
+      other => return Err(anyhow!("unknown duration unit: '{}'", other)),
+    };
+
+    let value: u64 = number
+      .parse()
+      .map_err(|_| anyhow!("invalid duration: '{}'", s))?;
+    total = value
+      .checked_mul(unit)
+      .and_then(|v| total.checked_add(v))
+      .ok_or_else(|| anyhow!("duration too large: '{}'", s))?;
+    number.clear();
+  }
+
+  if !number.is_empty() {
+    return Err(anyhow!("missing duration unit in '{}'", s));
+  }
+
+  log::trace!("parsed duration: {}s", total);
+  Ok(Duration::from_secs(total))
+}
+
 pub async fn parse_uid_perm(s: &str) -> anyhow::Result<(UserId, Permission)> {
   log::trace!("parsing user id and permission from '{}'", s);
-  let parts: Vec<&str> = s.trim().split_whitespace().collect();
+  let parts: Vec<&str> = s.split_whitespace().collect();
   if parts.len() != 2 {
     log::trace!("invalid role entry '{}'", s);
     return Err(anyhow!("invalid role entry: {}", s));
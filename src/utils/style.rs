@@ -24,10 +24,10 @@ pub async fn get_style(ctx: Weak<Mutex<Context>>) -> Arc<dyn DynStyle> {
   match ctx.upgrade() {
     Some(ctx) => {
       let ctx_guard = ctx.lock().await;
-      return ctx_guard.style.clone();
+      ctx_guard.style.clone()
     }
-    None => return Arc::new(DefaultStyle),
-  };
+    None => Arc::new(DefaultStyle),
+  }
 }
 
 pub struct DefaultStyle;